@@ -1,48 +1,30 @@
-use crate::util::constants::{NORMAL_SLEEP_TIME, SYSLOG_PROCESS_NAME, USING_SCENGEN};
+use crate::util::constants::{NANOSEC, SYSLOG_PROCESS_NAME, USING_SCENGEN};
 use kstat_rs::NamedData::Char as KChar;
 use kstat_rs::NamedData::String as KString;
 use kstat_rs::NamedData::UInt64;
 
+use crate::util::actuator::{Actuator, DryRunActuator, LiveActuator};
+use crate::util::cli::{self, Args};
+use crate::util::config::Config;
+use crate::util::errors::StatError;
 use crate::util::helpers;
-use kstat_rs::{Ctl, Data, Kstat, Named, NamedData, NamedType};
-use log::{debug, info};
-use simplelog::{ColorChoice, Config, LevelFilter, TermLogger, TerminalMode};
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::thread;
+use crate::util::original_bindings::OriginalBindings;
+use crate::util::sleep_time::{SleepState, SleepTime};
+use crate::util::stuck::Stuck;
+use crate::util::topology::{CpuInfo, Topology};
+use crate::util::types::{
+    Cookie, CpuId, CpuStat, CpuState, Delta, DeltaCpu, GotStat, Ivec, Move, Pinned, Plan,
+};
+use kstat_rs::{Ctl, Data, Named, NamedData};
+use log::{debug, error, info, trace, warn};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
 use std::time::Duration;
 mod util;
 use anyhow::anyhow;
 use anyhow::Context;
 
-// This will end up being something more sophisticated, I'm sure
-type Delta = usize;
-type Deltas = Vec<Delta>;
-type DeltasTotalTime = usize;
-
-struct Ivecs {
-    //     ->{<cookie#>}     iterates over pci_intrs::<nexus>:cookie
-    time: u64,       // pci_intrs:<ivec#>:<nexus>:time (in nsec)
-    pil: u64,        // pci_intrs:<ivec#>:<nexus>:pil
-    crtime: u64,     // pci_intrs:<ivec#>:<nexus>:crtime
-    ino: u64,        // pci_intrs:<ivec#>:<nexus>:ino
-    num_ino: u64, // num inos of single device instance sharing this entry. Will be > 1 on pcplusmp X86 systems for devices with multiple MSI interrupts.
-    buspath: String, // pci_intrs:<ivec#>:<nexus>:buspath
-    name: String, // pci_intrs:<ivec#>:<nexus>:name
-    ihs: u64,     // pci_intrs:<ivec#>:<nexus>:ihs
-}
-
-struct CpuStat {
-    tot: u64,    // cpu:<cpuid>:sys:cpu_nsec_{user + kernel + idle}
-    crtime: u64, // cpu:<cpuid>:sys:crtime
-    ivecs: Ivecs,
-}
-
-struct GotStat {
-    snaptime: u64,                 // kstat's snaptime
-    cpus: HashMap<CpuId, CpuStat>, // one hash reference per online cpu
-}
-
 // # getstat() is responsible for reading the kstats and generating a "stat" hash.
 // #
 // # generate_delta() is responsible for taking two "stat" hashes and creating
@@ -66,28 +48,92 @@ fn get_string_value(stat: &Named) -> anyhow::Result<String> {
     }
 }
 
-type CpuId = i32;
-
-fn online_cpus(ctl: &Ctl) -> anyhow::Result<Vec<CpuId>> {
-    Ok(ctl
-        .filter(Some("cpu_info"), None, None)
+// The state of every cpu the system knows about, on-line or not
+fn cpu_states(ctl: &Ctl) -> BTreeMap<CpuId, CpuState> {
+    ctl.filter(Some("cpu_info"), None, None)
         .filter_map(|mut cpu| {
             if let Ok(Data::Named(val)) = ctl.read(&mut cpu) {
-                val.iter().find(|x| x.name == "state").and_then(|c| {
-                    if get_string_value(c).ok()? == "on-line" {
-                        Some(cpu.ks_instance)
-                    } else {
-                        None
-                    }
-                })
+                val.iter()
+                    .find(|x| x.name == "state")
+                    .and_then(|c| get_string_value(c).ok())
+                    .map(|state| (cpu.ks_instance, CpuState::parse(&state)))
             } else {
                 None
             }
         })
-        .collect())
+        .collect()
+}
+
+// The ids of every cpu the system knows about. These only change when cpus are hot-plugged.
+fn cpu_ids(ctl: &Ctl) -> BTreeSet<CpuId> {
+    ctl.filter(Some("cpu_info"), None, None)
+        .map(|cpu| cpu.ks_instance)
+        .collect()
+}
+
+// Where each cpu sits in the hardware. A cpu whose cpu_info we can't make sense of is left out.
+fn get_topology(ctl: &Ctl) -> Topology {
+    let cpus = ctl
+        .filter(Some("cpu_info"), None, None)
+        .filter_map(|mut cpu| {
+            let val = match ctl.read(&mut cpu) {
+                Ok(Data::Named(val)) => val,
+                _ => return None,
+            };
+            let int = |k: &str| {
+                val.iter()
+                    .find(|n| n.name == k)
+                    .and_then(|n| match n.value {
+                        NamedData::Int64(v) => Some(v),
+                        _ => None,
+                    })
+            };
+            let svals = string_value_map(&val);
+
+            let info = CpuInfo {
+                chip_id: int("chip_id")?,
+                core_id: int("core_id")?,
+                clock_mhz: int("clock_MHz")?,
+                brand: svals.get("brand")?.to_string(),
+            };
+
+            Some((cpu.ks_instance, info))
+        })
+        .collect();
+
+    Topology::new(cpus)
+}
+
+// Log the topology, a line per socket
+fn dumptopology(topology: &Topology) {
+    info!("topology: {}", topology);
+
+    for (chip_id, cores) in topology.sockets() {
+        let info = cores
+            .values()
+            .flatten()
+            .next()
+            .and_then(|cpu_id| topology.cpu(*cpu_id));
+        let cores: Vec<String> = cores.values().map(|cpus| format!("{:?}", cpus)).collect();
+
+        if let Some(info) = info {
+            debug!(
+                "    socket {}: {} at {} MHz, cores {}",
+                chip_id,
+                info.brand,
+                info.clock_mhz,
+                cores.join(" ")
+            );
+        }
+    }
 }
 
-fn getstat(ctl: &Ctl, is_apic: bool) -> anyhow::Result<Option<GotStat>> {
+fn getstat(
+    ctl: &Ctl,
+    config: &Config,
+    is_apic: bool,
+    sleep_time: u64,
+) -> Result<GotStat, StatError> {
     // kstats are not generated atomically. Each kstat hierarchy will
     // have been generated within the kernel at a different time. On a
     // thrashing system, we may not run quickly enough in order to get
@@ -98,79 +144,255 @@ fn getstat(ctl: &Ctl, is_apic: bool) -> anyhow::Result<Option<GotStat>> {
     // total time taken up in getstat(). If this time approaches the
     // time between snapshots, our results may not be useful.
 
-    // let mut minsnap = -1;
-    // let mut maxsnap = -1;
-
-    // Hash of hash which matches (MSI device, ino) combos to kstats.
-    // my %msidevs = ();
+    // Matches (MSI device, ino) combos to the cpu and cookie of their ivecs.
+    let mut msidevs: MsiDevs = BTreeMap::new();
 
+    // Iterate over the cpus in cpu:<cpuid>::. Only "on-line" processors
+    // accept interrupts, but the others can still have interrupts the kernel couldn't move away,
+    // so we keep them too, along with their state.
+    //
     // Record cpu:<cpuid>:sys:snaptime, and check $minsnap/$maxsnap.
 
-    let online_cpus = online_cpus(&ctl)?;
+    let cpu_states = cpu_states(ctl);
 
-    if online_cpus.len() <= 1 {
-        return Ok(None);
+    if cpu_states.values().filter(|s| s.takes_interrupts()).count() <= 1 {
+        return Err(StatError::SingleCpu);
     }
 
     let mut snaptimes = Vec::new();
+    let mut cpus: BTreeMap<CpuId, CpuStat> = BTreeMap::new();
 
-    for cpu_id in &online_cpus {
-        let mut cpu_sys: Vec<_> = ctl
-            .filter(Some("cpu"), Some(*cpu_id), Some("sys"))
-            .collect();
-        // This is bound to be a single element vector
-        let mut this_cpu = cpu_sys
-            .first_mut()
-            .context(format!("failed to find cpu:{}:sys", cpu_id))?;
+    for (cpu_id, state) in &cpu_states {
+        // This is bound to be a single element. A cpu which isn't on-line might not have one.
+        let mut this_cpu = match ctl.filter(Some("cpu"), Some(*cpu_id), Some("sys")).next() {
+            Some(this_cpu) => this_cpu,
+            None if !state.takes_interrupts() => {
+                debug!("cpu {} is {}, and has no stats", cpu_id, state);
+                continue;
+            }
+            None => return Err(StatError::MissingCpuSys(*cpu_id)),
+        };
+
+        let val = match ctl.read(&mut this_cpu) {
+            Ok(Data::Named(val)) => val,
+            Ok(_) => return Err(StatError::MissingCpuSys(*cpu_id)),
+            Err(source) => {
+                return Err(StatError::KstatRead {
+                    kstat: format!("cpu:{}:sys", cpu_id),
+                    source,
+                })
+            }
+        };
 
-        snaptimes.push(this_cpu.ks_snaptime);
+        snaptimes.push(this_cpu.ks_snaptime as u64);
 
-        if let Ok(Data::Named(val)) = ctl.read(this_cpu) {
-            let vals = numeric_value_map(&val);
-            // I'm surprised we count idle time, but that's what the original does
-            let cpu_time: u64 = numeric_value_map(&val)
-                .iter()
-                .filter_map(|(k, v)| k.starts_with("cpu_nsec").then_some(*v))
-                .sum();
-            let cpu_crtime = vals.get("crtime").context("could not get crtime");
-        }
+        // I'm surprised we count idle time, but that's what the original does
+        let vals = numeric_value_map(&val);
+        let tot = ["cpu_nsec_idle", "cpu_nsec_user", "cpu_nsec_kernel"]
+            .iter()
+            .map(|k| {
+                vals.get(k).copied().ok_or_else(|| StatError::MissingField {
+                    kstat: format!("cpu:{}:sys", cpu_id),
+                    field: k.to_string(),
+                })
+            })
+            .sum::<Result<u64, StatError>>()?;
+
+        cpus.insert(
+            *cpu_id,
+            CpuStat {
+                state: state.clone(),
+                tot,
+                crtime: this_cpu.ks_crtime as u64,
+                ivecs: BTreeMap::new(),
+            },
+        );
     }
 
-    let mut pci_intrs: Vec<_> = ctl.filter(Some("pci_intrs"), None, None).collect();
+    // Iterate over the ivecs. If we have no stats for the cpu, ignore the ivecs mapped to it, if
+    // any.
+    //
+    // Record pci_intrs:{inum}:<nexus>:time, snaptime, crtime, pil, ino, name, and buspath.
 
-    for mut pci_intr in pci_intrs {
-        // println!("{:?}", pci_intr);
-        // let vals = numeric_value_map(&val);
-        // let mut this_cpu = pci_intr
-        //     .first_mut()
-        //     .context("failed to find pci_intr kstat")?;
-        // println!("----------------------");
-        // println!("{:#?}", this_cpu);
-        // for mut x in pci_intrs {
-        snaptimes.push(pci_intr.ks_snaptime);
+    for mut pci_intr in ctl.filter(Some("pci_intrs"), None, None) {
+        let kstat = format!("pci_intrs:{}:{}", pci_intr.ks_instance, pci_intr.ks_name);
 
-        if let Ok(Data::Named(val)) = ctl.read(&mut pci_intr) {
-            let nvals = numeric_value_map(&val);
-            let svals = string_value_map(&val);
-            let cpu_id = nvals.get("cpu").context("failed to get pci_intr cpu")?;
+        let val = match ctl.read(&mut pci_intr) {
+            Ok(Data::Named(val)) => val,
+            Ok(_) => continue,
+            Err(source) => return Err(StatError::KstatRead { kstat, source }),
+        };
 
-            if !&online_cpus.contains(&(*cpu_id as i32)) {
-                continue;
-            }
+        let nvals = numeric_value_map(&val);
+        let svals = string_value_map(&val);
+        let missing = |k: &str| StatError::MissingField {
+            kstat: kstat.clone(),
+            field: k.to_string(),
+        };
+        let num = |k: &str| nvals.get(k).copied().ok_or_else(|| missing(k));
+        let string = |k: &str| {
+            svals
+                .get(k)
+                .map(|v| v.to_string())
+                .ok_or_else(|| missing(k))
+        };
 
-            if *svals.get("type").context("failed to get pci_intr type")? == "disabled" {
-                continue;
+        let cpu_id = num("cpu")? as CpuId;
+        let cpu = match cpus.get_mut(&cpu_id) {
+            Some(cpu) => cpu,
+            None => continue,
+        };
+
+        let intr_type = string("type")?;
+
+        if intr_type == "disabled" {
+            continue;
+        }
+
+        snaptimes.push(pci_intr.ks_snaptime as u64);
+
+        let buspath = string("buspath")?;
+        let ino = num("ino")?;
+        let cookie = format!("{} {}", buspath, ino);
+
+        let time = num("time")?;
+        let crtime = pci_intr.ks_crtime as u64;
+        let name = string("name")?;
+
+        match cpu.ivecs.entry(cookie.clone()) {
+            Entry::Occupied(mut shared) => shared.get_mut().add_shared_handler(time, crtime, &name),
+            Entry::Vacant(slot) => {
+                if is_apic && intr_type == "msi" {
+                    msidevs
+                        .entry(name.clone())
+                        .or_default()
+                        .insert(ino, (cpu_id, cookie));
+                }
+
+                slot.insert(Ivec {
+                    time,
+                    pil: num("pil")?,
+                    crtime,
+                    ino,
+                    num_ino: 1,
+                    buspath,
+                    name,
+                    ihs: 1,
+                    pinned: false,
+                });
             }
         }
-        // }
     }
 
-    todo!()
+    // Only now do we know every handler sharing each ivec. Rules have to see each MSI vector
+    // before it's folded into its group, or one naming any but the lowest ino would be lost.
+    apply_pin_rules(&mut cpus, config);
+    consolidate_msi_groups(&mut cpus, &msidevs);
+
+    for (cpu_id, cpu) in &cpus {
+        for (cookie, ivec) in cpu.ivecs.iter().filter(|(_, i)| i.ihs > 1) {
+            debug!(
+                "cpu {}: \"{}\" is shared by {}",
+                cpu_id,
+                cookie,
+                ivec.handlers().join(", ")
+            );
+        }
+    }
+
+    // We define the timerange as the amount of time spent gathering the various kstats, divided
+    // by our sleeptime. If we take a lot of time to access the kstats, and then we create a delta
+    // comparing these kstats with a prior set of kstats, that delta will cover substantially
+    // different amount of time depending upon which interrupt or CPU is being examined.
+    //
+    // By checking the timerange here, we guarantee that any deltas created from these kstats will
+    // contain self-consistent data, in that all CPUs and interrupts cover a similar span of time.
+    //
+    // time_range_too_high is the upper bound. Any timerange above this is thrown out as garbage.
+    // If the stat is safely within this bound, we treat the stat as representing an instant in
+    // time, rather than the time range it actually spans. We arbitrarily choose minsnap as the
+    // snaptime of the stat.
+
+    let minsnap = snaptimes.iter().min().copied().unwrap_or_default();
+    let maxsnap = snaptimes.iter().max().copied().unwrap_or_default();
+    let timerange = (maxsnap - minsnap) as f64 / NANOSEC / sleep_time as f64;
+
+    if timerange > config.time_range_too_high {
+        return Err(StatError::IncoherentTimerange {
+            span: (maxsnap - minsnap) as f64 / NANOSEC,
+            timerange,
+            limit: config.time_range_too_high,
+        });
+    }
+
+    Ok(GotStat {
+        snaptime: minsnap,
+        cpus,
+    })
+}
+
+fn apply_pin_rules(cpus: &mut BTreeMap<CpuId, CpuStat>, config: &Config) {
+    for ivec in cpus.values_mut().flat_map(|cpu| cpu.ivecs.values_mut()) {
+        ivec.pinned = config.is_pinned(ivec);
+    }
+}
+
+// MSI device name -> ino -> where that ino's ivec lives
+type MsiDevs = BTreeMap<String, BTreeMap<u64, (CpuId, Cookie)>>;
+
+// All MSI interrupts of a device instance share a single MSI address. On X86 systems with an
+// APIC, this MSI address is interpreted as CPU routing info by the APIC. For this reason, on these
+// platforms, all interrupts for MSI devices must be moved to the same CPU at the same time.
+//
+// Since all interrupts will be on the same CPU on these platforms, all interrupts can be
+// consolidated into one ivec entry. For such devices, num_ino will be > 1 to denote that a group
+// move is needed, and pinning any of them pins the group. msidevs is only populated on APIC
+// systems, so this is a no-op elsewhere.
+fn consolidate_msi_groups(cpus: &mut BTreeMap<CpuId, CpuStat>, msidevs: &MsiDevs) {
+    for inos in msidevs.values() {
+        // inos are sorted lowest first. Fold the rest into the lowest one, assuming the PIL is
+        // the same for all of them.
+        let mut members = inos.values();
+
+        let (first_cpu, first_cookie) = match members.next() {
+            Some(first) => first,
+            None => continue,
+        };
+
+        let mut time = 0;
+        let mut crtime = 0;
+        let mut num_ino = 0;
+        let mut pinned = false;
+
+        for (cpu_id, cookie) in members {
+            if let Some(ivec) = cpus
+                .get_mut(cpu_id)
+                .and_then(|cpu| cpu.ivecs.get_mut(cookie))
+            {
+                time += ivec.time;
+                crtime = crtime.max(ivec.crtime);
+                num_ino += 1;
+                pinned |= ivec.pinned;
+                // Invalidate this cookie, less complicated than deleting it, and it keeps the
+                // ivec count stable for generate_delta().
+                ivec.num_ino = 0;
+            }
+        }
+
+        if let Some(first) = cpus
+            .get_mut(first_cpu)
+            .and_then(|cpu| cpu.ivecs.get_mut(first_cookie))
+        {
+            first.num_ino += num_ino;
+            first.time += time;
+            first.crtime = first.crtime.max(crtime);
+            first.pinned |= pinned;
+        }
+    }
 }
+
 type KstatNumericValueMap<'a> = HashMap<&'a str, u64>;
 type KstatStrinValueMap<'a> = HashMap<&'a str, &'a str>;
-// use kstat::Data::Named;
-// use kstat::NamedVal::UInt64;
 
 fn numeric_value_map<'a>(named: &'a [Named]) -> KstatNumericValueMap<'a> {
     named
@@ -182,197 +404,1418 @@ fn numeric_value_map<'a>(named: &'a [Named]) -> KstatNumericValueMap<'a> {
         .collect()
 }
 
+// Char kstats are fixed-size buffers. Perl looks beyond NULL chars, so the original truncates at
+// the first one, and so do we.
 fn string_value_map<'a>(named: &'a [Named]) -> KstatStrinValueMap<'a> {
     named
         .iter()
         .filter_map(|n| match n.value {
             KString(v) => Some((n.name, v)),
-            KChar(v) => Some((n.name, std::str::from_utf8(v).unwrap().trim_matches('\0'))),
+            KChar(v) => {
+                let end = v.iter().position(|b| *b == 0).unwrap_or(v.len());
+                std::str::from_utf8(&v[..end]).ok().map(|s| (n.name, s))
+            }
             _ => None,
         })
         .collect()
 }
 
-// generate_delta() is responsible for taking two "stat" hashes and creating a new "delta" hash
-// that represents what has changed over time.
-fn generate_delta() {
-    // 2 args
-    todo!()
-}
+// generate_delta() takes two stats, returned from getstat(), and creates a Delta. The Delta
+// contains the same basic info as stat and newstat, but with the times as deltas instead of
+// absolute times. If the two stats can't be compared, because CPUs or interrupts have come or
+// gone, or counters have gone backwards, the Delta is marked as missing.
+fn generate_delta(stat: &GotStat, newstat: &GotStat) -> Delta {
+    // Take the worstcase timerange
+    let minsnap = stat.snaptime;
+    let maxsnap = newstat.snaptime;
 
-// compress_deltas() is responsible for taking a list of deltas and generating a single delta hash that encompasses all the time periods described by the deltas.
-fn compress_deltas() {
-    // 1 arg
-    todo!()
-}
-fn dumpdelta() {
-    // 1 arg
-    todo!()
-}
-fn goodness() {
-    // 1 arg
-    todo!()
-}
-fn imbalanced() {
-    // 2 args
-    todo!()
-}
-fn do_reconfig() {
-    // 1 arg
-    todo!()
-}
-fn goodness_cpu() {
-    // 2 args
-    todo!()
-}
-fn move_intr() {
-    // 4 args
-    todo!()
-}
-fn ivecs_to_string() {
-    // 1 vec arg
-    todo!()
+    if maxsnap <= minsnap {
+        debug!("VERIFY: generate_delta: stats aren't ascending");
+        return Delta::missing(minsnap, maxsnap);
+    }
+
+    // if there are a different number of cpus in the stats, set missing
+
+    if stat.cpus.len() != newstat.cpus.len() {
+        debug!("VERIFY: generate_delta: number of CPUs changed");
+        return Delta::missing(minsnap, maxsnap);
+    }
+
+    let mut delta = Delta {
+        minsnap,
+        maxsnap,
+        ..Default::default()
+    };
+
+    let mut intrload = 0.0;
+    let mut intrnsec = 0;
+
+    // scan through every cpu in newstat and compare against stat
+
+    for (cpu_id, newcpst) in &newstat.cpus {
+        // If stat is missing a cpu from newstat, then it was just onlined. Mark missing.
+
+        let cpst = match stat.cpus.get(cpu_id) {
+            Some(cpst) if cpst.crtime == newcpst.crtime => cpst,
+            _ => {
+                debug!("VERIFY: generate_delta: cpu {} changed", cpu_id);
+                return Delta::missing(minsnap, maxsnap);
+            }
+        };
+
+        // A cpu which has just been taken out of service, or put back, changes what we'd do
+        // with it. Start again.
+
+        if cpst.state != newcpst.state {
+            debug!(
+                "generate_delta: cpu {} went from {} to {}",
+                cpu_id, cpst.state, newcpst.state
+            );
+            return Delta::missing(minsnap, maxsnap);
+        }
+
+        let mut tot = match newcpst.tot.checked_sub(cpst.tot) {
+            // Avoid remote chance of division by zero
+            Some(tot) => tot.max(1),
+            None => {
+                debug!("VERIFY: generate_delta: deltas are not ascending?");
+                return Delta::missing(minsnap, maxsnap);
+            }
+        };
+
+        // if the number of ivecs differs, set missing
+
+        if cpst.ivecs.len() != newcpst.ivecs.len() {
+            debug!(
+                "VERIFY: generate_delta: cpu {} has more/less interrupts",
+                cpu_id
+            );
+            return Delta::missing(minsnap, maxsnap);
+        }
+
+        let mut dltcpu = DeltaCpu {
+            state: newcpst.state.clone(),
+            ..Default::default()
+        };
+
+        // Unused cookies correspond to MSI vectors which are part of a group. The whole group is
+        // accounted for by a different cookie.
+        for (inum, newivec) in newcpst.ivecs.iter().filter(|(_, i)| i.num_ino > 0) {
+            // If this ivec doesn't exist in stat, or if stat shows a different crtime, set
+            // missing.
+            let ivec = match cpst.ivecs.get(inum) {
+                Some(ivec) if ivec.crtime == newivec.crtime => ivec,
+                _ => {
+                    debug!(
+                        "VERIFY: generate_delta: cpu {} inum {} has changed",
+                        cpu_id, inum
+                    );
+                    return Delta::missing(minsnap, maxsnap);
+                }
+            };
+
+            // calculate time used by this interrupt
+
+            let time = match newivec.time.checked_sub(ivec.time) {
+                Some(time) => time,
+                None => {
+                    debug!("VERIFY: generate_delta: ivec went backwards?");
+                    return Delta::missing(minsnap, maxsnap);
+                }
+            };
+
+            dltcpu.intrs += time;
+            dltcpu.bigintr = dltcpu.bigintr.max(time);
+
+            // Transfer over basic info about the kstat. We don't have to worry about
+            // discrepancies between ivec and newivec because we verified that both have the same
+            // crtime.
+
+            dltcpu.ivecs.insert(
+                inum.clone(),
+                Ivec {
+                    time,
+                    ..newivec.clone()
+                },
+            );
+        }
+
+        if tot < dltcpu.intrs {
+            // Ewww! Hopefully just a rounding error. Make something up.
+            tot = dltcpu.intrs;
+        }
+
+        dltcpu.tot = tot;
+        dltcpu.intrload = dltcpu.intrs as f64 / tot as f64;
+        intrload += dltcpu.intrload;
+        intrnsec += dltcpu.intrs;
+        delta.cpus.insert(*cpu_id, dltcpu);
+    }
+
+    if !delta.cpus.is_empty() {
+        let cpus = delta.cpus.len() as f64;
+        delta.avgintrload = intrload / cpus;
+        delta.avgintrnsec = intrnsec as f64 / cpus;
+    }
+
+    delta
 }
-fn do_find_goal() {
-    // 4 args
-    todo!()
+
+// The deltas covering (at least) the last statslen seconds, which we use as a moving average
+#[derive(Debug)]
+struct Deltas {
+    deltas: Vec<Delta>,
+    tottime: f64,  // sum of maxsnap-minsnap across deltas, in seconds
+    statslen: f64, // length of the window, in seconds (STATS_LEN unless told otherwise)
 }
-fn find_goal() {
-    // 2 args
-    todo!()
+
+impl Deltas {
+    fn new(statslen: u64) -> Self {
+        Self {
+            deltas: Vec::new(),
+            tottime: 0.0,
+            statslen: statslen as f64,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.deltas.clear();
+        self.tottime = 0.0;
+    }
+
+    // Incorporate a new delta. Returns true if we've just now received statslen seconds worth of
+    // deltas, in which case it's time to evaluate a reconfiguration.
+    fn push(&mut self, delta: Delta) -> bool {
+        let statslen = self.statslen;
+        let below_statslen = self.tottime < statslen;
+
+        self.tottime += delta.span();
+        self.deltas.push(delta);
+
+        // Remove old deltas if total time is more than statslen, but only if that doesn't cause
+        // us to fall below statslen seconds.
+        while self.deltas.len() > 1 {
+            let newtime = self.tottime - self.deltas[0].span();
+
+            if newtime < statslen {
+                break;
+            }
+
+            self.deltas.remove(0);
+            self.tottime = newtime;
+        }
+
+        below_statslen && self.tottime >= statslen
+    }
+
+    fn is_full(&self) -> bool {
+        self.tottime >= self.statslen
+    }
 }
-fn do_reconfig_cpu2cpu() {
-    // 4 args
-    todo!()
+
+// Deal with getstat() failing part way through the loop. Whenever the stats we're holding have
+// to go, so does the window they've been feeding, or the next delta would be compressed together
+// with deltas describing a system which no longer exists.
+fn stat_failed(
+    e: &StatError,
+    stat: &mut Option<GotStat>,
+    deltas: &mut Deltas,
+    sleep_time: &mut SleepTime,
+) {
+    match e {
+        // Nothing to do with 1 CPU
+        StatError::SingleCpu => sleep_time.transition(SleepState::SingleCpu),
+        e => {
+            debug!("getstat failed: {}", e);
+
+            // If the kstats changed under us, what we're holding can't be compared with the
+            // next snapshot. Otherwise, sleep and try again, hoping the problem will clear up.
+            if !e.resets_window() {
+                return;
+            }
+        }
+    }
+
+    *stat = None;
+    deltas.clear();
 }
-fn do_reconfig_cpu() {
-    // 3 args
-    todo!()
+
+// compress_deltas() takes a list of deltas, and returns a single new delta which represents the
+// combined information from all the deltas. The deltas provided are assumed to be sequential in
+// time. The resulting compressed delta looks just like any other delta. This new delta is also
+// more accurate since its statistics are averaged over a longer period than any of the original
+// deltas.
+fn compress_deltas(deltas: &[Delta]) -> Option<Delta> {
+    let (first, last) = match (deltas.first(), deltas.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => {
+            debug!("VERIFY: compress_deltas: list of delta is empty?");
+            return None;
+        }
+    };
+
+    let mut newdelta = Delta {
+        minsnap: first.minsnap,
+        maxsnap: last.maxsnap,
+        ..Default::default()
+    };
+
+    let mut intrs = 0;
+    let mut tot = 0;
+
+    for delta in deltas {
+        if delta.missing {
+            debug!("VERIFY: compressing bad deltas?");
+            return None;
+        }
+
+        for (cpu_id, cpu) in &delta.cpus {
+            intrs += cpu.intrs;
+            tot += cpu.tot;
+
+            let newcpu = newdelta.cpus.entry(*cpu_id).or_default();
+            newcpu.state = cpu.state.clone();
+            newcpu.intrs += cpu.intrs;
+            newcpu.tot += cpu.tot;
+
+            for (inum, ivec) in &cpu.ivecs {
+                newcpu
+                    .ivecs
+                    .entry(inum.clone())
+                    .and_modify(|newivec| newivec.time += ivec.time)
+                    .or_insert_with(|| ivec.clone());
+            }
+        }
+    }
+
+    for cpu in newdelta.cpus.values_mut() {
+        cpu.tot = cpu.tot.max(1);
+        cpu.bigintr = cpu.ivecs.values().map(|i| i.time).max().unwrap_or_default();
+        cpu.intrload = cpu.intrs as f64 / cpu.tot as f64;
+    }
+
+    if !newdelta.cpus.is_empty() {
+        newdelta.avgintrnsec = intrs as f64 / newdelta.cpus.len() as f64;
+        newdelta.avgintrload = intrs as f64 / tot.max(1) as f64;
+    }
+
+    Some(newdelta)
 }
 
-fn main() -> anyhow::Result<()> {
-    helpers::setup_logger().context("Failed to instantiate logger")?;
-    let debug = helpers::in_debug_mode()?;
+// dumpdelta() logs the delta structure in a nice, human readable display.
+fn dumpdelta(delta: &Delta) {
+    // print global info
 
-    if debug {
-        debug!("{} is starting (debug)", SYSLOG_PROCESS_NAME);
-    } else {
-        info!("{} is starting", SYSLOG_PROCESS_NAME);
+    debug!("dumpdelta:");
+
+    if delta.missing {
+        debug!(" RECONFIGURATION IN DELTA");
     }
 
-    let gotsig = helpers::setup_signal_handler();
-
-    // let mut deltas = Vec::new();
-    // my @deltas = ();
-    // let mut deltas_tottime = 0;
-    // my $deltas_tottime = 0;		# sum of maxsnap-minsnap across @deltas
-    // let avggoodness;
-    // my $avggoodness;
-    // let mut baseline_goodness = 0;
-    // my $baseline_goodness = 0;
-    // let compdelta;
-    // my $compdelta;
-    // let do_reconfig;
-    // my $do_reconfig;
-    // d
-    // // # temp variables
-    // let goodness;
-    // // my $goodness;
-    // let deltatime;
-    // // my $deltatime;
-    // let olddelta;
-    // // my $olddelta;
-    // let olddeltatime;
-    // // my $olddeltatime;
-    // let delta;
-    // // my $delta;
-    // let newstat;
-    // // my $newstat;
-    // let below_statslen;
-    // // my $below_statslen;
-    // let newtime;
-    // // my $newtime;
-    // let ret;
-    // my $ret;
-    //
-    let mut ctl = Ctl::new().context("Cannot get kstat handle")?;
-    let mut intr_stats: Vec<_> = ctl.filter(Some("pci_intrs"), None, None).collect();
+    debug!(
+        " avgintrload: {:5.2}%  avgintrnsec: {}",
+        delta.avgintrload * 100.0,
+        delta.avgintrnsec as u64
+    );
 
-    // From inside loop
-    let stats = getstat(&ctl, true);
+    if let Some(goodness) = delta.goodness {
+        debug!("    goodness: {:5.2}%", goodness * 100.0);
+    }
 
-    // # If no pci_intrs kstats were found, we need to exit, but we can't because
-    // # SMF will restart us and/or report an error to the administrator. But
-    // # there's nothing an administrator can do. So print out a message for SMF
-    // # logs and silently pause forever.
+    // iterate over cpus
 
-    let first_stat = match intr_stats.first_mut() {
-        Some(stat) => stat,
-        None => {
-            eprintln!(
-                "{}: no interrupts were found; your PCI bus may not yet be supported",
-                SYSLOG_PROCESS_NAME,
+    for (cpu_id, cpst) in &delta.cpus {
+        let tot = cpst.tot as f64;
+        debug!(
+            "    cpu {:3} intr {:7.3}%  (bigintr {:7.3}%)",
+            cpu_id,
+            cpst.intrload * 100.0,
+            cpst.bigintr as f64 * 100.0 / tot
+        );
+        debug!("        intrs {}, bigintr {}", cpst.intrs, cpst.bigintr);
+
+        if !cpst.state.takes_interrupts() {
+            debug!("        state {}", cpst.state);
+        }
+
+        // iterate over ivecs on this cpu, listing the pinned ones after the rest
+
+        let dumpivec = |ivec_id: &Cookie, ivst: &Ivec| {
+            let name = if ivst.ihs > 1 {
+                format!("{}({})", ivst.name, ivst.ihs)
+            } else {
+                ivst.name.clone()
+            };
+
+            debug!(
+                "    {:>15}:\"{}\": {:7.3}%  {}  (pil {})",
+                name,
+                ivec_id,
+                ivst.time as f64 * 100.0 / tot,
+                ivst.time,
+                ivst.pil
             );
+        };
+        let (pinned, movable): (Vec<_>, Vec<_>) =
+            cpst.ivecs.iter().partition(|(_, ivst)| ivst.pinned);
+
+        for (ivec_id, ivst) in movable {
+            dumpivec(ivec_id, ivst);
+        }
+
+        if !pinned.is_empty() {
+            debug!("        pinned: {}", cpst.pinned());
 
-            while !gotsig.load(Ordering::Relaxed) {
-                std::thread::sleep(Duration::from_millis(100));
+            for (ivec_id, ivst) in pinned {
+                dumpivec(ivec_id, ivst);
             }
+        }
+    }
+}
 
-            std::process::exit(0);
+// goodness() and its helper goodness_cpu() return a heuristic which describes how good (or bad)
+// the current interrupt balance is. The value returned will be between 0 and 1, with 0
+// representing maximum goodness, and 1 representing maximum badness.
+//
+// goodness is determined by evaluating the goodness of each individual cpu, and returning the
+// worst case. This helps on systems with many CPUs, where otherwise a single pathological CPU
+// might otherwise be ignored because the average was OK.
+fn goodness(delta: &Delta, config: &Config) -> f64 {
+    if delta.missing {
+        return 1.0;
+    }
+
+    let mut high_goodness: f64 = 0.0;
+    let (avgintrload, _) = eligible_averages(delta, config);
+
+    for (cpu_id, cpu) in &delta.cpus {
+        // Anything which could be moved off an ineligible cpu is as bad as it gets. Whatever is
+        // pinned there has to be left be, and says nothing about the balance elsewhere.
+        if ineligible(*cpu_id, cpu, config).is_some() {
+            if cpu.ivecs.values().any(|ivec| !ivec.pinned) {
+                return 1.0;
+            }
+            continue;
         }
-    };
 
-    // # See if this is a system with a pcplusmp APIC.
-    // # Such systems will get special handling.
-    // # Assume that if one bus has a pcplusmp APIC that they all do.
+        let goodness = goodness_cpu(cpu, avgintrload, config);
 
-    let is_apic = helpers::is_apic_system(&ctl, first_stat)?;
+        if !(0.0..=1.0).contains(&goodness) {
+            debug!("VERIFY: goodness: cpu goodness out of range?");
+            dumpdelta(delta);
+            return 1.0;
+        }
 
-    debug!("APIC system: {:?}", is_apic);
-    let mut sleep_time = NORMAL_SLEEP_TIME;
+        if goodness == 1.0 {
+            return 1.0; // worst case, no need to continue
+        }
 
-    while !gotsig.load(Ordering::SeqCst) {
-        // clear deltas
-        // let mut deltas: Deltas = Vec::new();
-        // let mut deltas_total_time: DeltasTotalTime = 0;
+        high_goodness = high_goodness.max(goodness);
+    }
 
-        debug!("loop!");
-        // let mut stat = 0;
+    high_goodness
+}
 
-        // 1. Sleep, update the kstats, and save the new stats in $newstat.
-        //
-        if USING_SCENGEN {
-            debug!("scenario generator is not implemented");
+// Why interrupts have to be moved off cpu_id, and none given to it, if they do. That's any cpu
+// which isn't on-line, and any the config excludes.
+fn ineligible(cpu_id: CpuId, cpu: &DeltaCpu, config: &Config) -> Option<String> {
+    if !cpu.state.takes_interrupts() {
+        Some(cpu.state.to_string())
+    } else if config.exclude_cpus.contains(cpu_id) {
+        Some("excluded".to_string())
+    } else {
+        None
+    }
+}
+
+// Whether any cpu in states could be given interrupts under config
+fn any_eligible(states: &BTreeMap<CpuId, CpuState>, config: &Config) -> bool {
+    states
+        .iter()
+        .any(|(cpu_id, state)| state.takes_interrupts() && !config.exclude_cpus.contains(*cpu_id))
+}
+
+// A config which excludes every on-line cpu would otherwise only show up as each reconfiguration
+// being aborted, so say so as soon as it's read.
+fn check_eligible(ctl: &Ctl, config: &Config) {
+    if !any_eligible(&cpu_states(ctl), config) {
+        warn!(
+            "no on-line cpu is eligible to take interrupts (exclude_cpus: {})",
+            config.exclude_cpus
+        );
+    }
+}
+
+// The average interrupt load and time across the cpus which may be given interrupts. Everything
+// on an ineligible cpu that isn't pinned there is on its way to one of those, so it's counted as
+// if it had already arrived.
+fn eligible_averages(delta: &Delta, config: &Config) -> (f64, f64) {
+    if delta
+        .cpus
+        .iter()
+        .all(|(cpu_id, cpu)| ineligible(*cpu_id, cpu, config).is_none())
+    {
+        return (delta.avgintrload, delta.avgintrnsec);
+    }
+
+    let (mut intrs, mut tot, mut ncpus) = (0, 0, 0);
+
+    for (cpu_id, cpu) in &delta.cpus {
+        if ineligible(*cpu_id, cpu, config).is_some() {
+            intrs += cpu.intrs.saturating_sub(cpu.pinned());
         } else {
-            thread::sleep(Duration::from_secs(sleep_time));
-            ctl = ctl.update()?;
+            intrs += cpu.intrs;
+            tot += cpu.tot;
+            ncpus += 1;
         }
+    }
 
-        let stats = getstat(&ctl, is_apic);
+    if ncpus == 0 {
+        return (0.0, 0.0);
+    }
 
-        // # 2. Compare $newstat with the prior set of values, result in %$delta.
+    (
+        intrs as f64 / tot.max(1) as f64,
+        intrs as f64 / ncpus as f64,
+    )
+}
 
-        // # 3. If $delta->{missing}, then there has been a reconfiguration of
-        // # either cpus or interrupts (probably both). We need to toss out our
-        // # old set of statistics and start from scratch.
-        // #
-        // # Also, if the delta covers a very long range of time, then we've
-        // # been experiencing a system overload that has resulted in intrd
-        // # not being allowed to run effectively for a while now. As above,
-        // # toss our old statistics and start from scratch.
+// imbalanced() is used by the main routine to determine if the goodness has shifted far enough
+// from our last baseline to warrant a reassignment of interrupts. A very high goodness indicates
+// that a CPU is way out of whack. If the goodness has varied too much since the baseline, then
+// perhaps a reconfiguration is worth considering.
+fn imbalanced(goodness: f64, baseline: f64, config: &Config) -> bool {
+    // We are pathological, or creeping away from the baseline
+    goodness > config.goodness_pathological
+        || (goodness - baseline).abs() > config.goodness_mindelta
+}
+// do_reconfig(), do_reconfig_cpu(), and do_reconfig_cpu2cpu(), are the decision-making functions
+// responsible for generating a new interrupt distribution. They are designed with the definition
+// of goodness() in mind, i.e. they use the same definition of "good distribution" as does
+// goodness().
+//
+// do_reconfig() is responsible for deciding whether a redistribution is actually warranted. If
+// the goodness is already pretty good, it doesn't waste the CPU time to generate a new
+// distribution. If it calculates a new distribution and finds that it is not sufficiently
+// improved from the prior distribution, it will not do the redistribution, mainly to avoid the
+// disruption to system performance caused by rejuggling interrupts.
+//
+// Its main loop works by going through a list of cpus sorted from highest to lowest interrupt
+// load. It removes the highest-load cpus one at a time and hands them off to do_reconfig_cpu().
+// This function then re-sorts the remaining CPUs from lowest to highest interrupt load, and one
+// at a time attempts to rejuggle interrupts between the original high-load CPU and the low-load
+// CPU. Rejuggling on a high-load CPU is considered finished as soon as its interrupt load is
+// within goodness_mindelta of the average interrupt load. Such a CPU will have a goodness of
+// below the goodness_mindelta threshold.
+//
+// Cpus which aren't on-line, or which the config excludes, are no part of that. Before the main
+// loop, evacuate() moves everything which isn't pinned off them, and they're never chosen as a
+// target afterwards.
+//
+// Unlike the original, nothing here touches the running system. All the juggling is done on a
+// copy of the delta, and if it's worth doing, the result is returned as a Plan for
+// apply_plan() to carry out. Ok(None) means the current config is optimal (or close enough).
+fn do_reconfig(delta: &Delta, config: &Config) -> anyhow::Result<Option<Plan>> {
+    let oldgoodness = delta.goodness.unwrap_or_else(|| goodness(delta, config));
 
-        // # 4. Incorporate new delta into the list of deltas, and associated
-        // # statistics. If we've just now received $statslen deltas, then it's
-        // # time to evaluate a reconfiguration.
-        //
+    // We can't improve goodness to better than 0. We should stop here if, even if we achieve a
+    // goodness of 0, the improvement is still too small to merit the action.
+
+    if oldgoodness < config.goodness_mindelta {
+        debug!("goodness good enough, don't reconfig");
+        return Ok(None);
+    }
+
+    if delta.missing {
+        return Err(anyhow!(
+            "RECONFIG Aborted: should not have a delta with missing"
+        ));
+    }
+
+    // Make a list of all the cpuids which may be given interrupts, and remember where every ivec
+    // started out. From here on, the averages are those of the eligible cpus alone.
+
+    let mut newdelta = delta.clone();
+    let mut cpusortlist: Vec<CpuId> = newdelta
+        .cpus
+        .iter()
+        .filter(|(cpu_id, cpu)| ineligible(**cpu_id, cpu, config).is_none())
+        .map(|(cpu_id, _)| *cpu_id)
+        .collect();
+    let origcpus: BTreeMap<Cookie, CpuId> = newdelta
+        .cpus
+        .iter()
+        .flat_map(|(cpu_id, cpu)| cpu.ivecs.keys().map(move |inum| (inum.clone(), *cpu_id)))
+        .collect();
+
+    if cpusortlist.is_empty() {
+        return Err(anyhow!(
+            "RECONFIG Aborted: no cpu is eligible to take interrupts"
+        ));
+    }
+
+    info!("Optimizing interrupt assignments");
+
+    (newdelta.avgintrload, newdelta.avgintrnsec) = eligible_averages(&newdelta, config);
+    evacuate(&mut newdelta, &cpusortlist, config);
+
+    let mut budget = NodeBudget::new(config);
+
+    // Sort the list of CPUs from highest to lowest interrupt load. Remove the top CPU from that
+    // list and attempt to redistribute its interrupts. If the CPU has a goodness below a
+    // threshold, just ignore the CPU and move to the next one. If the CPU's load falls below the
+    // average load plus that same threshold, then there are no CPUs left worth reconfiguring,
+    // and we're done.
+
+    while !cpusortlist.is_empty() {
+        // Re-sort cpusortlist each time, since do_reconfig_cpu can move interrupts around.
+
+        cpusortlist.sort_by(|a, b| {
+            newdelta.cpus[b]
+                .intrload
+                .total_cmp(&newdelta.cpus[a].intrload)
+        });
+
+        let cpu_id = cpusortlist.remove(0);
+        let cpu = &newdelta.cpus[&cpu_id];
+
+        if cpu.intrload <= config.goodness_unsafe_load
+            && cpu.intrload <= newdelta.avgintrload + config.goodness_mindelta
+        {
+            debug!(
+                "finished reconfig: cpu {} load {} avgload {}",
+                cpu_id, cpu.intrload, newdelta.avgintrload
+            );
+            break;
+        }
+
+        if goodness_cpu(cpu, newdelta.avgintrload, config) < config.goodness_mindelta {
+            continue;
+        }
+
+        do_reconfig_cpu(
+            &mut newdelta,
+            &origcpus,
+            &cpusortlist,
+            cpu_id,
+            config,
+            &mut budget,
+        );
+    }
+
+    if budget.exhausted {
+        info!(
+            "search budget of {} nodes ran out; settling for a good enough assignment",
+            budget.max_nodes
+        );
+    }
+
+    // How good a job did we do? If the improvement was minimal, and our goodness wasn't
+    // pathological (and thus needing any help it can get), then don't bother moving the
+    // interrupts.
+
+    let newgoodness = goodness(&newdelta, config);
+
+    if newgoodness > oldgoodness {
+        debug!("VERIFY: reconfig: result has worse goodness?");
+    }
+
+    let moves: Vec<Move> = newdelta
+        .cpus
+        .iter()
+        .flat_map(|(cpu_id, cpu)| {
+            cpu.ivecs
+                .iter()
+                .filter(|(inum, _)| origcpus[*inum] != *cpu_id)
+                .map(|(inum, ivec)| Move {
+                    inum: inum.clone(),
+                    name: ivec.name.clone(),
+                    buspath: ivec.buspath.clone(),
+                    ino: ivec.ino,
+                    num_ino: ivec.num_ino,
+                    from: origcpus[inum],
+                    to: *cpu_id,
+                })
+        })
+        .collect();
+
+    // Getting interrupts off cpus which shouldn't have any is worth doing however little it does
+    // for goodness, which could be stuck at 1.0 because of some other cpu we can't help.
+    let evacuating = moves
+        .iter()
+        .any(|mv| ineligible(mv.from, &delta.cpus[&mv.from], config).is_some());
+
+    if !evacuating
+        && (oldgoodness != 1.0 || newgoodness == 1.0)
+        && oldgoodness - newgoodness < config.goodness_mindelta
+    {
+        debug!("goodness already near optimum, don't reconfig");
+        return Ok(None);
+    }
+
+    debug!(
+        "goodness {:5.2}% --> {:5.2}%",
+        oldgoodness * 100.0,
+        newgoodness * 100.0
+    );
+
+    let pinned = delta
+        .cpus
+        .iter()
+        .flat_map(|(cpu_id, cpu)| {
+            cpu.ivecs
+                .iter()
+                .filter(|(_, ivec)| ivec.pinned)
+                .map(|(inum, ivec)| Pinned {
+                    inum: inum.clone(),
+                    name: ivec.name.clone(),
+                    cpu: *cpu_id,
+                })
+        })
+        .collect();
+
+    Ok(Some(Plan {
+        goodness: oldgoodness,
+        newgoodness,
+        moves,
+        pinned,
+        nodes: budget.nodes,
+    }))
+}
+
+// Move everything which can be moved off the ineligible cpus, biggest first, each to whichever
+// eligible cpu is least loaded at the time. do_reconfig() then balances the eligible cpus as
+// usual, so this only needs to be a reasonable start.
+fn evacuate(delta: &mut Delta, eligible: &[CpuId], config: &Config) {
+    let mut stranded: Vec<(CpuId, String, Cookie, u64)> = delta
+        .cpus
+        .iter()
+        .filter_map(|(cpu_id, cpu)| Some((cpu_id, cpu, ineligible(*cpu_id, cpu, config)?)))
+        .flat_map(|(cpu_id, cpu, why)| {
+            cpu.ivecs
+                .iter()
+                .filter(|(_, ivec)| !ivec.pinned)
+                .map(move |(inum, ivec)| (*cpu_id, why.clone(), inum.clone(), ivec.time))
+        })
+        .collect();
+
+    stranded.sort_by_key(|(_, _, _, time)| std::cmp::Reverse(*time));
+
+    for (cpu_id, why, inum, _) in stranded {
+        let tgtcpu_id = *eligible
+            .iter()
+            .min_by(|a, b| delta.cpus[a].intrload.total_cmp(&delta.cpus[b].intrload))
+            .expect("evacuate: no eligible cpus");
+
+        info!(
+            "\"{}\" is stranded on cpu {} ({}); evacuating it to cpu {}",
+            inum, cpu_id, why, tgtcpu_id
+        );
+        move_intr(delta, &inum, cpu_id, tgtcpu_id);
+    }
+}
+
+// Time to move those interrupts! Returns the moves which failed, if any. Each move which is made
+// is noted in bindings, so that it can be undone later.
+fn apply_plan(
+    plan: &Plan,
+    actuator: &mut dyn Actuator,
+    bindings: &mut OriginalBindings,
+) -> Vec<Move> {
+    let mut failed = Vec::new();
+    let mut warned = false;
+
+    for mv in &plan.moves {
+        debug!("moving {}", mv);
+
+        match actuator.move_interrupt(mv) {
+            // Nothing to put back for a move which was only ever pretend
+            Ok(()) if actuator.is_live() => bindings.moved(mv),
+            Ok(()) => {}
+            Err(e) => {
+                if !warned {
+                    warn!("Unable to move interrupts");
+                    warned = true;
+                }
+
+                debug!(
+                    "Unable to move buspath {} ino {} to cpu {}: {}",
+                    mv.buspath, mv.ino, mv.to, e
+                );
+                failed.push(mv.clone());
+            }
+        }
+    }
+
+    failed
+}
+// To calculate the goodness of an individual CPU, we start by looking at its load due to
+// interrupts. If the load is above a certain high threshold and there is more than one interrupt
+// assigned to this CPU, we set goodness to worst-case. If the load is below the average interrupt
+// load of all CPUs, then we return best-case, since what's to complain about?
+//
+// Otherwise we look at how much the load is above the average, and return that as the goodness,
+// with one caveat: we never return more than the CPU's interrupt load ignoring its largest single
+// interrupt source. This is because a CPU with one high-load interrupt, and no other interrupts,
+// is perfectly balanced. Nothing can be done to improve the situation, and thus it is perfectly
+// balanced even if the interrupt's load is 100%.
+fn goodness_cpu(cpu: &DeltaCpu, avgintrload: f64, config: &Config) -> f64 {
+    let load = cpu.intrs as f64 / cpu.tot as f64;
+
+    if load < avgintrload {
+        return 0.0; // low loads are perfectly good
+    }
+
+    // load_no_bigintr represents the load due to interrupts, excluding the one biggest interrupt.
+    // This is the most gain we can get on this CPU from offloading interrupts. Pinned interrupts
+    // can't be offloaded at all, so if there are more of those than the biggest interrupt, they
+    // set the limit instead.
+
+    let load_no_bigintr = (cpu.intrs - cpu.bigintr.max(cpu.pinned())) as f64 / cpu.tot as f64;
+
+    // A major imbalance is indicated if a CPU is saturated with interrupt handling, and it has
+    // more than one source of interrupts. Those other interrupts could be starved if of a lower
+    // pil. Return a goodness of 1, which is the worst possible return value, which will
+    // effectively contaminate this entire delta.
+
+    if load > config.goodness_unsafe_load && cpu.ivecs.len() > 1 && cpu.pinned() < cpu.intrs {
+        return 1.0;
+    }
+
+    (load - avgintrload).min(load_no_bigintr)
+}
+// move_intr() is used by reconfiguration code to move an interrupt between cpus within a delta.
+// This manipulates data structures, and does not actually move the interrupt on the running
+// system.
+fn move_intr(delta: &mut Delta, inum: &Cookie, oldcpu_id: CpuId, newcpu_id: CpuId) {
+    // Remove ivec from old cpu
+
+    let oldcpu = delta
+        .cpus
+        .get_mut(&oldcpu_id)
+        .expect("move_intr: no such source cpu");
+    let ivec = oldcpu
+        .ivecs
+        .remove(inum)
+        .expect("move_intr: ivec is not on source cpu");
+
+    if ivec.time > oldcpu.bigintr {
+        debug!("VERIFY: move_intr: intr's time > bigintr?");
+    }
+
+    oldcpu.intrs -= ivec.time;
+    oldcpu.intrload = oldcpu.intrs as f64 / oldcpu.tot as f64;
+
+    if ivec.time >= oldcpu.bigintr {
+        oldcpu.bigintr = oldcpu
+            .ivecs
+            .values()
+            .map(|i| i.time)
+            .max()
+            .unwrap_or_default();
+    }
+
+    // Add ivec onto new cpu
+
+    let newcpu = delta
+        .cpus
+        .get_mut(&newcpu_id)
+        .expect("move_intr: no such target cpu");
+
+    newcpu.intrs += ivec.time;
+    newcpu.intrload = newcpu.intrs as f64 / newcpu.tot as f64;
+    newcpu.bigintr = newcpu.bigintr.max(ivec.time);
+    newcpu.ivecs.insert(inum.clone(), ivec);
+}
+
+fn move_intr_check(delta: &Delta, oldcpu_id: CpuId, newcpu_id: CpuId) {
+    if delta.cpus[&oldcpu_id].tot < delta.cpus[&oldcpu_id].intrs {
+        debug!("VERIFY: Moved interrupts left 100+% load on src cpu");
+    }
+
+    if delta.cpus[&newcpu_id].tot < delta.cpus[&newcpu_id].intrs {
+        debug!("VERIFY: Moved interrupts left 100+% load on tgt cpu");
+    }
+}
+
+// An ivec which do_reconfig_cpu2cpu() is considering placing on one of two cpus
+#[derive(Debug)]
+struct Candidate {
+    inum: Cookie,
+    time: u64,
+    nowcpu: CpuId,
+}
+
+fn ivecs_to_string<'a, I>(ivecs: I) -> String
+where
+    I: IntoIterator<Item = &'a Candidate>,
+{
+    ivecs
+        .into_iter()
+        .map(|i| i.inum.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// find_goal() and its helper do_find_goal() are used to find the best combination of interrupts
+// in order to generate a load that is as close as possible to a goal load without falling below
+// that goal. If even every interrupt together falls short of the goal, the best fit is all of
+// them.
+//
+// The arguments to find_goal are a list of ivecs, sorted by descending time, and the goal load.
+// The goal is relative to time.
+//
+// The original did an exhaustive include/exclude depth-first search, which is exponential in the
+// number of ivecs: fine for a handful of PCI devices, not for a pile of NIC queues and NVMe
+// vectors. This is a branch and bound search of the same tree, seeded with a greedy fit, which
+// prunes any branch that can't reach the goal or can't beat the best fit so far, and gives up
+// once the budget runs out. When it gives up, the best fit found so far is still a valid answer,
+// just not necessarily the closest.
+#[derive(Debug)]
+struct GoalFit {
+    goals: Vec<bool>, // whether each ivec is part of the best fit
+    load: u64,        // total load of the best fit
+    nodes: usize,     // how many nodes of the search tree were visited
+    complete: bool,   // false if the search ran out of budget
+}
+
+// do_reconfig() can call find_goal() for every pair of cpus, so the find_goal_max_nodes budget
+// is shared between all the searches of one pass, which gives a reconfiguration a ceiling however
+// many cpus there are. Once it's spent, each search settles for its greedy fit.
+#[derive(Debug)]
+struct NodeBudget {
+    max_nodes: usize, // find_goal_max_nodes
+    nodes: usize,     // nodes visited so far, across every search
+    exhausted: bool,  // some search gave up before it was done
+}
+
+impl NodeBudget {
+    fn new(config: &Config) -> Self {
+        Self {
+            max_nodes: config.find_goal_max_nodes,
+            nodes: 0,
+            exhausted: false,
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.max_nodes.saturating_sub(self.nodes)
+    }
+}
+
+fn find_goal(ivecs: &[Candidate], goal: f64, budget: &mut NodeBudget) -> GoalFit {
+    let total: u64 = ivecs.iter().map(|i| i.time).sum();
+
+    let mut fit = GoalFit {
+        goals: vec![false; ivecs.len()],
+        load: 0,
+        nodes: 0,
+        complete: true,
+    };
+
+    if goal <= 0.0 {
+        // the empty set will best meet the goal
+    } else if total as f64 <= goal {
+        // Including everything still leaves us at or below the goal. There's nothing better to be
+        // done.
+        fit.goals = vec![true; ivecs.len()];
+        fit.load = total;
+    } else {
+        trace!(
+            "finding goal {} from intrs {}",
+            goal,
+            ivecs_to_string(ivecs)
+        );
+
+        // loads[i] will equal the total loads of all ivecs[i..], so we can tell when a branch can
+        // no longer reach the goal.
+
+        let mut loads: Vec<u64> = ivecs
+            .iter()
+            .rev()
+            .scan(0, |tot, ivec| {
+                *tot += ivec.time;
+                Some(*tot)
+            })
+            .collect();
+        loads.reverse();
+
+        // Greedily take the biggest interrupts until we reach the goal. That's the first fit the
+        // exhaustive search would have found, and gives us something to beat.
+
+        for (idx, ivec) in ivecs.iter().enumerate() {
+            if fit.load as f64 >= goal {
+                break;
+            }
+            fit.goals[idx] = true;
+            fit.load += ivec.time;
+        }
+
+        let mut search = GoalSearch {
+            ivecs,
+            loads: &loads,
+            goal,
+            max_nodes: budget.remaining(),
+            chosen: vec![false; ivecs.len()],
+            fit: &mut fit,
+        };
+
+        search.do_find_goal(0, 0);
+        budget.nodes += fit.nodes;
+
+        if !fit.complete {
+            budget.exhausted = true;
+            debug!(
+                "find_goal: gave up after {} nodes, using load {} for goal {}",
+                fit.nodes, fit.load, goal
+            );
+        }
+
+        if (fit.load as f64) < goal {
+            debug!("VERIFY: find_goal didn't meet goals");
+        }
+    }
+
+    trace!(
+        "goals found: {} (load {}, {} nodes searched)",
+        ivecs_to_string(
+            ivecs
+                .iter()
+                .zip(&fit.goals)
+                .filter(|(_, goal)| **goal)
+                .map(|(ivec, _)| ivec)
+        ),
+        fit.load,
+        fit.nodes
+    );
+
+    fit
+}
+
+struct GoalSearch<'a> {
+    ivecs: &'a [Candidate],
+    loads: &'a [u64],
+    goal: f64,
+    max_nodes: usize,  // give up after searching this many nodes
+    chosen: Vec<bool>, // the branch we're currently on
+    fit: &'a mut GoalFit,
+}
+
+impl GoalSearch<'_> {
+    // Nothing can beat a load which is the smallest whole number of nsec at or above the goal
+    fn is_optimal(&self) -> bool {
+        self.fit.load as f64 >= self.goal && (self.fit.load as f64) - self.goal < 1.0
+    }
+
+    // do_find_goal() is passed an index, originally 0, and the load of the interrupts chosen so
+    // far on this branch. It considers including ivecs[idx], then excluding it, and records any
+    // combination which meets the goal with less load than the best so far. Returns false when
+    // the search should stop altogether.
+    fn do_find_goal(&mut self, idx: usize, load: u64) -> bool {
+        if self.fit.nodes >= self.max_nodes {
+            self.fit.complete = false;
+            return false;
+        }
+
+        self.fit.nodes += 1;
+
+        // Once we're at or above the goal, there is no need to consider further interrupts since
+        // they'll only take us further from the goal.
+
+        if load as f64 >= self.goal {
+            if load < self.fit.load {
+                trace!("{}: new best load {}", idx, load);
+                self.fit.load = load;
+                self.fit.goals.clone_from(&self.chosen);
+            }
+            return !self.is_optimal();
+        }
+
+        // If including all remaining interrupts can't get us to the goal, this branch is no good.
+
+        if idx >= self.ivecs.len() || ((load + self.loads[idx]) as f64) < self.goal {
+            return true;
+        }
+
+        let with = load + self.ivecs[idx].time;
+
+        // Evaluate the "with" option, unless it can't beat what we already have
+
+        if with < self.fit.load {
+            self.chosen[idx] = true;
+            let carry_on = self.do_find_goal(idx + 1, with);
+            self.chosen[idx] = false;
+
+            if !carry_on {
+                return false;
+            }
+        }
+
+        // Evaluate the "without" option
+
+        self.do_find_goal(idx + 1, load)
+    }
+}
+
+// We've been asked to consider interrupt juggling between srccpu_id (with a high interrupt load)
+// and tgtcpu_id (with a lower interrupt load).
+fn do_reconfig_cpu2cpu(
+    delta: &mut Delta,
+    origcpus: &BTreeMap<Cookie, CpuId>,
+    srccpu_id: CpuId,
+    tgtcpu_id: CpuId,
+    srcload: f64,
+    budget: &mut NodeBudget,
+) {
+    debug!("exchanging intrs between {} and {}", srccpu_id, tgtcpu_id);
+
+    // Gather together all the ivecs from both CPUs and sort by load, highest first. Pinned ivecs
+    // aren't going anywhere, so they're no part of the choice.
+
+    let mut ivecs: Vec<Candidate> = [srccpu_id, tgtcpu_id]
+        .iter()
+        .flat_map(|cpu_id| {
+            delta.cpus[cpu_id]
+                .ivecs
+                .iter()
+                .filter(|(_, ivec)| !ivec.pinned)
+                .map(move |(inum, ivec)| Candidate {
+                    inum: inum.clone(),
+                    time: ivec.time,
+                    nowcpu: *cpu_id,
+                })
+        })
+        .collect();
+
+    if ivecs.is_empty() {
+        return;
+    }
+
+    ivecs.sort_by_key(|ivec| std::cmp::Reverse(ivec.time));
+
+    // Our "goal" load for srccpu_id is the average load across all CPUs. find_goal() will
+    // determine the optimum selection of the available interrupts which comes closest to this
+    // goal without falling below the goal.
+    //
+    // We know that the interrupt load on tgtcpu_id is less than that on srccpu_id, but its load
+    // could still be above avgintrnsec. Don't choose a goal which would bring srccpu_id below
+    // the load on tgtcpu_id.
+
+    let avgnsec = (delta.cpus[&srccpu_id].intrs + delta.cpus[&tgtcpu_id].intrs) as f64 / 2.0;
+    let mut goal = delta.avgintrnsec.max(avgnsec);
+
+    // Whatever is pinned to srccpu_id counts towards its goal before we choose anything.
+
+    let pinned = delta.cpus[&srccpu_id].pinned();
+    goal -= pinned as f64;
+
+    // If the largest of the interrupts is on srccpu_id, leave it there. This can help minimize
+    // the disruption caused by moving interrupts. Pinned interrupts already anchor srccpu_id
+    // though, and keeping the largest on top of them could leave it stuck well above the goal.
+
+    if pinned == 0 && origcpus[&ivecs[0].inum] == srccpu_id {
+        debug!("Keeping {} on {}", ivecs[0].inum, srccpu_id);
+        goal -= ivecs[0].time as f64;
+        ivecs.remove(0);
+    }
+
+    debug!("GOAL: inums should total {}", goal);
+    let goals = find_goal(&ivecs, goal, budget).goals;
+
+    // find_goal() tells us whether each ivec should be on srccpu_id, or on tgtcpu_id. Call
+    // move_intr() to update our delta with the new results.
+
+    for (ivec, on_src) in ivecs.iter().zip(goals) {
+        trace!("ivec {} goal {}", ivec.inum, on_src);
+
+        if on_src && ivec.nowcpu != srccpu_id {
+            move_intr(delta, &ivec.inum, ivec.nowcpu, srccpu_id);
+        } else if !on_src && ivec.nowcpu != tgtcpu_id {
+            move_intr(delta, &ivec.inum, ivec.nowcpu, tgtcpu_id);
+        }
+    }
+
+    move_intr_check(delta, srccpu_id, tgtcpu_id);
+
+    let src = &delta.cpus[&srccpu_id];
+    let newload = src.intrs as f64 / src.tot as f64;
+
+    if newload > srcload || newload <= delta.avgintrload {
+        debug!("VERIFY: cpu2cpu: new load didn't end up in expected range");
+    }
+}
+
+// We have been asked to rejuggle interrupts between oldcpu_id and other CPUs found on
+// cpusortlist so as to improve the load on oldcpu_id. We walk cpusortlist backwards, from lowest
+// to highest interrupt load, and attempt to rejuggle interrupts between the two CPUs. Don't do
+// this if the other CPU has a higher load than oldcpu_id. We're done rejuggling once
+// oldcpu_id's goodness falls below a threshold.
+fn do_reconfig_cpu(
+    delta: &mut Delta,
+    origcpus: &BTreeMap<Cookie, CpuId>,
+    cpusortlist: &[CpuId],
+    oldcpu_id: CpuId,
+    config: &Config,
+    budget: &mut NodeBudget,
+) {
+    debug!("reconfiguring {}", oldcpu_id);
+
+    let avgintrload = delta.avgintrload;
+
+    for tgtcpu_id in cpusortlist.iter().rev() {
+        let cpu = &delta.cpus[&oldcpu_id];
+
+        if goodness_cpu(cpu, avgintrload, config) < config.goodness_mindelta {
+            break;
+        }
+
+        let load = cpu.intrload;
+
+        if delta.cpus[tgtcpu_id].intrload > load {
+            break;
+        }
+
+        do_reconfig_cpu2cpu(delta, origcpus, oldcpu_id, *tgtcpu_id, load, budget);
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}: {}\n{}", SYSLOG_PROCESS_NAME, e, cli::usage());
+            std::process::exit(2);
+        }
+    };
+
+    if args.help {
+        println!("{}", cli::usage());
+        return Ok(());
+    }
+
+    helpers::setup_logger(args.log_level()).context("Failed to instantiate logger")?;
+    let debug = args.debug > 0;
+
+    if debug {
+        debug!("{} is starting (debug)", SYSLOG_PROCESS_NAME);
+    } else {
+        info!("{} is starting", SYSLOG_PROCESS_NAME);
+    }
+
+    if args.dry_run {
+        info!("dry run: interrupt moves will be logged but not made");
+    }
+
+    if args.restore {
+        info!("original interrupt bindings will be restored on exit");
+    }
+
+    let mut config = args.config().context("Cannot load configuration")?;
+    info!("configuration: {}", config);
+
+    if args.one_shot {
+        info!("one shot: exiting after the first full window");
+    }
+
+    let shutdown = helpers::setup_signal_handler();
+
+    let mut actuator: Box<dyn Actuator> = if args.dry_run {
+        Box::new(DryRunActuator)
+    } else {
+        Box::new(LiveActuator)
+    };
+
+    let mut ctl = Ctl::new().context("Cannot get kstat handle")?;
+    let mut intr_stats: Vec<_> = ctl.filter(Some("pci_intrs"), None, None).collect();
+
+    // # If no pci_intrs kstats were found, we need to exit, but we can't because
+    // # SMF will restart us and/or report an error to the administrator. But
+    // # there's nothing an administrator can do. So print out a message for SMF
+    // # logs and silently pause forever.
+
+    let first_stat = match intr_stats.first_mut() {
+        Some(stat) => stat,
+        None => {
+            eprintln!(
+                "{}: no interrupts were found; your PCI bus may not yet be supported",
+                SYSLOG_PROCESS_NAME,
+            );
+
+            shutdown.wait();
+
+            std::process::exit(0);
+        }
+    };
+
+    // # See if this is a system with a pcplusmp APIC.
+    // # Such systems will get special handling.
+    // # Assume that if one bus has a pcplusmp APIC that they all do.
+
+    let is_apic = helpers::is_apic_system(&ctl, first_stat, actuator.as_ref())?;
+
+    debug!("APIC system: {:?}", is_apic);
+    check_eligible(&ctl, &config);
+    let mut cpus = cpu_ids(&ctl);
+    let mut topology = get_topology(&ctl);
+    dumptopology(&topology);
+    let mut sleep_time = SleepTime::new(&config);
+    let mut deltas = Deltas::new(config.stats_len);
+    let mut baseline_goodness = 0.0;
+    let mut would_have_moved: u64 = 0; // plans made but not applied, in dry-run mode
+    let mut bindings = OriginalBindings::default();
+    let mut stuck = Stuck::default();
+    let mut stat = match getstat(&ctl, &config, is_apic, sleep_time.secs()) {
+        Ok(stat) => {
+            bindings.record(&stat);
+            Some(stat)
+        }
+        Err(StatError::SingleCpu) => {
+            sleep_time.transition(SleepState::SingleCpu);
+            None
+        }
+        Err(e) => {
+            debug!("getstat failed: {}", e);
+            None
+        }
+    };
+
+    // Anything which ends the loop early goes here, so that the bindings are still restored on
+    // the way out.
+    let mut result = Ok(());
+
+    while !shutdown.requested() {
+        debug!("loop!");
+
+        // A single CPU system will never need reconfiguring, so there's nothing to wait for
+        if args.one_shot && sleep_time.state() == SleepState::SingleCpu {
+            info!("only one CPU is on-line; nothing to do");
+            break;
+        }
+        // let mut stat = 0;
+
+        // 1. Sleep, update the kstats, and save the new stats in $newstat.
+        //
+        if USING_SCENGEN {
+            debug!("scenario generator is not implemented");
+        } else {
+            shutdown.sleep(Duration::from_secs(sleep_time.secs()));
+
+            if shutdown.requested() {
+                break;
+            }
+
+            ctl = match ctl.update() {
+                Ok(ctl) => ctl,
+                Err(e) => {
+                    result = Err(e).context("Cannot update kstat handle");
+                    break;
+                }
+            };
+
+            // cpus have been hot-plugged
+            let newcpus = cpu_ids(&ctl);
+
+            if newcpus != cpus {
+                info!("cpus have been added or removed");
+                cpus = newcpus;
+                topology = get_topology(&ctl);
+                dumptopology(&topology);
+            }
+        }
+
+        // Keep what we've sampled so far unless the window itself has changed length, or the
+        // ivecs which are pinned have changed, in which case start filling a new one from
+        // scratch.
+        if shutdown.take_reload() {
+            match args.config() {
+                Ok(newconfig) if newconfig == config => info!("configuration unchanged"),
+                Ok(newconfig) => {
+                    info!("configuration: {}", newconfig);
+                    check_eligible(&ctl, &newconfig);
+                    sleep_time.reconfigure(&newconfig);
+
+                    // The deltas we have say which ivecs were pinned under the old rules
+                    if newconfig.stats_len != config.stats_len || newconfig.pin != config.pin {
+                        debug!("window length or pins changed, starting a new window");
+                        deltas = Deltas::new(newconfig.stats_len);
+                        stat = None;
+                    }
+
+                    // What couldn't be moved off an excluded cpu may be welcome there now
+                    if newconfig.exclude_cpus != config.exclude_cpus {
+                        stuck.clear();
+                    }
+
+                    config = newconfig;
+                }
+                Err(e) => error!("cannot reload configuration, keeping the old one: {}", e),
+            }
+        }
+
+        let mut newstat = match getstat(&ctl, &config, is_apic, sleep_time.secs()) {
+            Ok(newstat) => newstat,
+            Err(e) => {
+                stat_failed(&e, &mut stat, &mut deltas, &mut sleep_time);
+                continue;
+            }
+        };
+
+        bindings.record(&newstat);
+        stuck.pin(&mut newstat);
+
+        // CPUs have come on-line. We'll find out soon enough whether we're idle, but nothing
+        // from before they did is any use.
+        if sleep_time.state() == SleepState::SingleCpu {
+            sleep_time.transition(SleepState::Normal);
+            deltas.clear();
+        }
+
+        // # 2. Compare $newstat with the prior set of values, result in %$delta.
         //
+        // $stat could be empty if it's uninitialized, or if getstat() failed. If so, move
+        // $newstat to $stat, sleep and try again.
+
+        let delta = match &stat {
+            Some(stat) => generate_delta(stat, &newstat),
+            None => {
+                stat = Some(newstat);
+                continue;
+            }
+        };
+
+        if debug {
+            dumpdelta(&delta); // Dump most recent stats to the log.
+        }
+
+        stat = Some(newstat); // The new stats now become the old stats.
+
+        // # 3. If $delta->{missing}, then there has been a reconfiguration of
+        // # either cpus or interrupts (probably both). We need to toss out our
+        // # old set of statistics and start from scratch.
+        // #
+        // # Also, if the delta covers a very long range of time, then we've
+        // # been experiencing a system overload that has resulted in intrd
+        // # not being allowed to run effectively for a while now. As above,
+        // # toss our old statistics and start from scratch.
+
+        if delta.missing || delta.span() > deltas.statslen {
+            deltas.clear();
+            stat = None; // prevent next generate_delta() from setting missing
+            debug!("evaluating interrupt assignments");
+            continue;
+        }
+
+        // # 4. Incorporate new delta into the list of deltas, and associated
+        // # statistics. If we've just now received $statslen deltas, then it's
+        // # time to evaluate a reconfiguration.
         //
         // # 5. Remove old deltas if total time is more than $statslen. We use
         // # @deltas as a moving average of the last $statslen seconds. Shift
         // # off the olders deltas, but only if that doesn't cause us to fall
         // # below $statslen seconds.
-        //
-        //
+
+        let want_reconfig = deltas.push(delta);
+
         // # 6. The brains of the operation are here. First, check if we're
         // # imbalanced, and if so set $do_reconfig. If $do_reconfig is set,
         // # either because of imbalance or above in step 4, we evaluate a
@@ -391,7 +1834,1094 @@ fn main() -> anyhow::Result<()> {
         // #
         // # If $ret is 0, then nothing has happened because we're already
         // # good enough. Set baseline_goodness to current goodness.
+
+        let mut compdelta = match compress_deltas(&deltas.deltas) {
+            Some(compdelta) => compdelta,
+            None => {
+                debug!("VERIFY: couldn't compress deltas");
+                deltas.clear();
+                stat = None;
+                continue;
+            }
+        };
+
+        sleep_time.after_load(compdelta.high_intrload());
+
+        let goodness = goodness(&compdelta, &config);
+        compdelta.goodness = Some(goodness);
+
+        if debug {
+            dumpdelta(&compdelta);
+        }
+
+        debug!("GOODNESS: {:5.2}%", goodness * 100.0);
+
+        let want_reconfig =
+            want_reconfig || (deltas.is_full() && imbalanced(goodness, baseline_goodness, &config));
+
+        if want_reconfig {
+            let ok = match do_reconfig(&compdelta, &config) {
+                Ok(None) => {
+                    debug!("setting new baseline of {}", goodness);
+                    baseline_goodness = goodness;
+                    None
+                }
+                Ok(Some(plan)) => {
+                    info!(
+                        "{} {} interrupt(s), goodness {:5.2}% --> {:5.2}%",
+                        if args.dry_run { "would move" } else { "moving" },
+                        plan.moves.len(),
+                        plan.goodness * 100.0,
+                        plan.newgoodness * 100.0
+                    );
+                    debug!("find_goal() searched {} node(s) in all", plan.nodes);
+
+                    for pinned in &plan.pinned {
+                        info!("leaving {}", pinned);
+                    }
+                    // A signal which arrives now has to wait until the whole batch is done,
+                    // so we never leave a plan half applied.
+                    let failed = {
+                        let _retargeting = shutdown.retargeting();
+                        apply_plan(&plan, actuator.as_mut(), &mut bindings)
+                    };
+                    let ok = failed.is_empty();
+
+                    // Whatever wouldn't leave a cpu it shouldn't be on is left there from now on
+                    for mv in &failed {
+                        let cpu = &compdelta.cpus[&mv.from];
+
+                        if ineligible(mv.from, cpu, &config).is_some() {
+                            stuck.failed(mv, &cpu.state);
+                        }
+                    }
+
+                    if shutdown.requested() {
+                        info!(
+                            "finished {} {} interrupt(s){} before exiting",
+                            if args.dry_run {
+                                "planning to move"
+                            } else {
+                                "moving"
+                            },
+                            plan.moves.len(),
+                            if ok { "" } else { ", with errors" }
+                        );
+                    }
+
+                    if args.dry_run {
+                        would_have_moved += 1;
+                        info!(
+                            "dry run: would have reconfigured {} time(s) so far",
+                            would_have_moved
+                        );
+                    } else {
+                        info!("Interrupt assignments optimized");
+                    }
+
+                    Some(ok)
+                }
+                Err(e) => {
+                    warn!("{}", e);
+                    Some(false)
+                }
+            };
+
+            // Whether or not the moves worked, the stats we have no longer describe the
+            // system, so start again from scratch.
+            if let Some(ok) = ok {
+                deltas.clear();
+                stat = None;
+
+                if !ok {
+                    debug!("do_reconfig FAILED!");
+                }
+            }
+        }
+
+        debug!("---------------------------------------");
+
+        if args.one_shot && want_reconfig {
+            break;
+        }
+    }
+
+    if args.restore {
+        let _retargeting = shutdown.retargeting();
+
+        if !bindings.restore(actuator.as_mut()) {
+            warn!("Unable to restore all original interrupt bindings");
+        }
+    }
+
+    info!("{} is exiting", SYSLOG_PROCESS_NAME);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::actuator::RecordingActuator;
+
+    // A delta cpu with tot nsec of load, and one ivec per entry in times
+    fn delta_cpu(tot: u64, times: &[u64]) -> DeltaCpu {
+        let intrs = times.iter().sum();
+
+        DeltaCpu {
+            state: CpuState::OnLine,
+            intrs,
+            tot,
+            bigintr: times.iter().copied().max().unwrap_or_default(),
+            intrload: intrs as f64 / tot as f64,
+            ivecs: times
+                .iter()
+                .enumerate()
+                .map(|(i, time)| {
+                    (
+                        format!("/pci@0,0 {}", i),
+                        Ivec {
+                            time: *time,
+                            pil: 5,
+                            crtime: 0,
+                            ino: i as u64,
+                            num_ino: 1,
+                            buspath: "/pci@0,0".to_string(),
+                            name: format!("drv#{}", i),
+                            ihs: 1,
+                            pinned: false,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    // A delta made up of the given cpus, numbered from 0. Each cpu gets its own bus, so cookies
+    // are unique.
+    fn delta(cpus: Vec<DeltaCpu>) -> Delta {
+        let intrs: u64 = cpus.iter().map(|c| c.intrs).sum();
+        let tot: u64 = cpus.iter().map(|c| c.tot).sum();
+
+        Delta {
+            avgintrload: intrs as f64 / tot as f64,
+            avgintrnsec: intrs as f64 / cpus.len() as f64,
+            cpus: cpus
+                .into_iter()
+                .enumerate()
+                .map(|(cpu_id, mut cpu)| {
+                    cpu.ivecs = cpu
+                        .ivecs
+                        .into_values()
+                        .map(|mut ivec| {
+                            ivec.buspath = format!("/pci@{},0", cpu_id);
+                            (format!("{} {}", ivec.buspath, ivec.ino), ivec)
+                        })
+                        .collect();
+                    (cpu_id as CpuId, cpu)
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    // All the interrupt load on cpu 0, and none on cpu 1
+    fn two_cpu_skewed_delta() -> Delta {
+        delta(vec![
+            delta_cpu(1000, &[300, 200, 100]),
+            delta_cpu(1000, &[]),
+        ])
+    }
+
+    // A cpu as getstat() would have read it, with one ivec per entry in times
+    fn cpu_stat(tot: u64, times: &[u64]) -> CpuStat {
+        CpuStat {
+            state: CpuState::OnLine,
+            tot,
+            crtime: 0,
+            ivecs: delta_cpu(tot, times).ivecs,
+        }
+    }
+
+    fn assert_close(expected: f64, actual: f64, case: &str) {
+        assert!(
+            (expected - actual).abs() < 1e-9,
+            "{}: expected {}, got {}",
+            case,
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_goodness_cpu() {
+        let cases = [
+            ("below average", delta_cpu(1000, &[100]), 0.2, 0.0),
+            ("one big ivec", delta_cpu(1000, &[950]), 0.1, 0.0),
+            ("unsafe, shared", delta_cpu(1000, &[900, 50]), 0.1, 1.0),
+            (
+                "unsafe at threshold",
+                delta_cpu(1000, &[850, 50]),
+                0.1,
+                0.05,
+            ),
+            ("above average", delta_cpu(1000, &[200, 200, 100]), 0.3, 0.2),
+            ("capped by bigintr", delta_cpu(1000, &[400, 100]), 0.1, 0.1),
+            ("at average", delta_cpu(1000, &[150, 150]), 0.3, 0.0),
+        ];
+
+        for (case, cpu, avgintrload, expected) in cases {
+            assert_close(
+                expected,
+                goodness_cpu(&cpu, avgintrload, &Config::default()),
+                case,
+            );
+        }
+    }
+
+    #[test]
+    fn test_goodness() {
+        let cases = [
+            (
+                "balanced",
+                delta(vec![delta_cpu(1000, &[100, 100]), delta_cpu(1000, &[200])]),
+                0.0,
+            ),
+            (
+                "worst cpu wins",
+                delta(vec![
+                    delta_cpu(1000, &[300, 300]),
+                    delta_cpu(1000, &[100]),
+                    delta_cpu(1000, &[100]),
+                ]),
+                0.3,
+            ),
+            (
+                "contaminated",
+                delta(vec![delta_cpu(1000, &[500, 450]), delta_cpu(1000, &[])]),
+                1.0,
+            ),
+            ("no cpus", delta(vec![]), 0.0),
+        ];
+
+        for (case, delta, expected) in cases {
+            assert_close(expected, goodness(&delta, &Config::default()), case);
+        }
+
+        let missing = Delta::missing(0, 1);
+        assert_close(1.0, goodness(&missing, &Config::default()), "missing");
+    }
+
+    #[test]
+    fn test_imbalanced() {
+        let cases = [
+            ("pathological", 0.51, 0.51, true),
+            ("at pathological", 0.50, 0.45, false),
+            ("creeping up", 0.25, 0.1, true),
+            ("creeping down", 0.1, 0.25, true),
+            ("steady", 0.3, 0.25, false),
+        ];
+
+        for (case, goodness, baseline, expected) in cases {
+            assert_eq!(
+                expected,
+                imbalanced(goodness, baseline, &Config::default()),
+                "{}",
+                case
+            );
+        }
+    }
+
+    fn candidates(times: &[u64]) -> Vec<Candidate> {
+        times
+            .iter()
+            .enumerate()
+            .map(|(i, time)| Candidate {
+                inum: format!("/pci@0,0 {}", i),
+                time: *time,
+                nowcpu: 0,
+            })
+            .collect()
+    }
+
+    fn budget() -> NodeBudget {
+        NodeBudget::new(&Config::default())
+    }
+
+    #[test]
+    fn test_find_goal() {
+        let cases = [
+            (
+                "exact fit",
+                vec![50, 40, 30, 20],
+                60.0,
+                vec![false, true, false, true],
+            ),
+            ("all below goal", vec![30, 20], 100.0, vec![true, true]),
+            ("nothing wanted", vec![30, 20], 0.0, vec![false, false]),
+            (
+                "closest above",
+                vec![50, 40, 30],
+                45.0,
+                vec![true, false, false],
+            ),
+            ("nothing fits", vec![], 10.0, vec![]),
+        ];
+
+        for (case, times, goal, expected) in cases {
+            assert_eq!(
+                expected,
+                find_goal(&candidates(&times), goal, &mut budget()).goals,
+                "{}",
+                case
+            );
+        }
+    }
+
+    #[test]
+    fn test_find_goal_matches_exhaustive_search() {
+        // A small LCG, so the cases are the same every run
+        let mut seed: u64 = 42;
+        let mut rand = |max: u64| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (seed >> 33) % max
+        };
+
+        for _ in 0..200 {
+            let mut times: Vec<u64> = (0..rand(12) + 1).map(|_| rand(1000) + 1).collect();
+            times.sort_by_key(|t| std::cmp::Reverse(*t));
+            let total: u64 = times.iter().sum();
+            let goal = rand(total + 100) as f64;
+
+            let best = (0..1u32 << times.len())
+                .map(|mask| {
+                    times
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| mask & (1 << i) != 0)
+                        .map(|(_, t)| t)
+                        .sum::<u64>()
+                })
+                .filter(|load| *load as f64 >= goal)
+                .min()
+                .unwrap_or(total);
+
+            let fit = find_goal(&candidates(&times), goal, &mut budget());
+            let load: u64 = times
+                .iter()
+                .zip(&fit.goals)
+                .filter(|(_, g)| **g)
+                .map(|(t, _)| t)
+                .sum();
+
+            assert!(fit.complete);
+            assert_eq!(best, fit.load, "{:?} goal {}", times, goal);
+            assert_eq!(fit.load, load);
+        }
+    }
+
+    #[test]
+    fn test_find_goal_is_bounded() {
+        // Awkward loads which never add up to the goal exactly, so the search can't stop early
+        let times: Vec<u64> = (0..64).map(|i| 1_000_003 - i * 7919).collect();
+        let goal = times.iter().sum::<u64>() as f64 / 2.0 + 0.5;
+        let mut budget = budget();
+        let fit = find_goal(&candidates(&times), goal, &mut budget);
+
+        assert!(!fit.complete && budget.exhausted);
+        assert_eq!(budget.max_nodes, budget.nodes);
+        assert!(fit.load as f64 >= goal);
+
+        // The budget is for a whole pass, so once it's spent, later searches settle straight away
+        let fit = find_goal(&candidates(&times), goal, &mut budget);
+        assert_eq!(0, fit.nodes);
+        assert_eq!(budget.max_nodes, budget.nodes);
+        assert!(fit.load as f64 >= goal);
+
+        // and a reconfiguration searches no more than that, in total
+        let config = Config {
+            find_goal_max_nodes: 1,
+            ..Config::default()
+        };
+        let plan = do_reconfig(&two_cpu_skewed_delta(), &config)
+            .unwrap()
+            .unwrap();
+        assert!(plan.nodes <= 1);
+    }
+
+    #[test]
+    fn test_do_reconfig_balanced() {
+        let delta = delta(vec![delta_cpu(1000, &[100, 100]), delta_cpu(1000, &[200])]);
+        assert!(do_reconfig(&delta, &Config::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_do_reconfig_missing() {
+        assert!(do_reconfig(&Delta::missing(0, 1), &Config::default()).is_err());
+    }
+
+    #[test]
+    fn test_do_reconfig_spreads_load() {
+        let delta = two_cpu_skewed_delta();
+        let plan = do_reconfig(&delta, &Config::default()).unwrap().unwrap();
+
+        assert_close(0.3, plan.goodness, "goodness before");
+        assert_close(0.0, plan.newgoodness, "goodness after");
+
+        // The biggest interrupt stays put, and the rest go to the idle cpu
+        let moved: Vec<_> = plan
+            .moves
+            .iter()
+            .map(|m| (m.inum.as_str(), m.from, m.to))
+            .collect();
+
+        assert_eq!(vec![("/pci@0,0 1", 0, 1), ("/pci@0,0 2", 0, 1)], moved);
+    }
+
+    #[test]
+    fn test_do_reconfig_leaves_input_alone() {
+        let delta = two_cpu_skewed_delta();
+        do_reconfig(&delta, &Config::default()).unwrap();
+
+        assert_eq!(3, delta.cpus[&0].ivecs.len());
+        assert!(delta.cpus[&1].ivecs.is_empty());
+    }
+
+    // Bind every ivec in the delta where the delta says it is
+    fn recorder_for(delta: &Delta) -> RecordingActuator {
+        let mut actuator = RecordingActuator::default();
+
+        for (cpu_id, cpu) in &delta.cpus {
+            for ivec in cpu.ivecs.values() {
+                actuator.bind(&ivec.buspath, ivec.ino, *cpu_id);
+            }
+        }
+
+        actuator
+    }
+
+    #[test]
+    fn test_apply_plan() {
+        let delta = two_cpu_skewed_delta();
+        let mut actuator = recorder_for(&delta);
+        let plan = do_reconfig(&delta, &Config::default()).unwrap().unwrap();
+
+        assert!(apply_plan(&plan, &mut actuator, &mut OriginalBindings::default()).is_empty());
+        assert_eq!(plan.moves, actuator.moves);
+        assert_eq!(Some(0), actuator.cpu_of("/pci@0,0", 0));
+        assert_eq!(Some(1), actuator.cpu_of("/pci@0,0", 1));
+        assert_eq!(Some(1), actuator.cpu_of("/pci@0,0", 2));
+    }
+
+    #[test]
+    fn test_apply_plan_with_stale_bindings() {
+        let delta = two_cpu_skewed_delta();
+        let mut actuator = recorder_for(&delta);
+        let plan = do_reconfig(&delta, &Config::default()).unwrap().unwrap();
+
+        // Someone else got there first
+        actuator.bind("/pci@0,0", 1, 1);
+
+        let failed = apply_plan(&plan, &mut actuator, &mut OriginalBindings::default());
+        assert_eq!(
+            vec!["/pci@0,0 1"],
+            failed.iter().map(|mv| mv.inum.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(1, actuator.moves.len());
+        assert_eq!(Some(1), actuator.cpu_of("/pci@0,0", 2));
+    }
+
+    // A stat with the same cpus and ivecs as delta
+    fn stat_of(delta: &Delta) -> GotStat {
+        GotStat {
+            snaptime: 0,
+            cpus: delta
+                .cpus
+                .iter()
+                .map(|(cpu_id, cpu)| {
+                    let cpustat = CpuStat {
+                        state: cpu.state.clone(),
+                        tot: cpu.tot,
+                        crtime: 0,
+                        ivecs: cpu.ivecs.clone(),
+                    };
+                    (*cpu_id, cpustat)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_restore_original_bindings() {
+        let delta = two_cpu_skewed_delta();
+        let mut actuator = recorder_for(&delta);
+        let mut bindings = OriginalBindings::default();
+        let stat = stat_of(&delta);
+
+        bindings.record(&stat);
+        assert!(bindings.restore_moves().is_empty());
+
+        let plan = do_reconfig(&delta, &Config::default()).unwrap().unwrap();
+        assert!(apply_plan(&plan, &mut actuator, &mut bindings).is_empty());
+
+        let restore = bindings.restore_moves();
+        assert_eq!(plan.moves.len(), restore.len());
+        assert!(restore.iter().all(|mv| mv.from == 1 && mv.to == 0));
+
+        assert!(bindings.restore(&mut actuator));
+        assert!(bindings.restore_moves().is_empty());
+        for ino in 0..3 {
+            assert_eq!(Some(0), actuator.cpu_of("/pci@0,0", ino));
+        }
+    }
+
+    #[test]
+    fn test_dry_run_records_no_moves() {
+        let delta = two_cpu_skewed_delta();
+        let mut bindings = OriginalBindings::default();
+        bindings.record(&stat_of(&delta));
+
+        // With -n -r, there's nothing to restore on the way out
+        let plan = do_reconfig(&delta, &Config::default()).unwrap().unwrap();
+        assert!(apply_plan(&plan, &mut DryRunActuator, &mut bindings).is_empty());
+        assert!(bindings.restore_moves().is_empty());
+    }
+
+    // Pin ino on cpu_id's bus, as getstat() would have if a rule matched it
+    fn pin(delta: &mut Delta, cpu_id: CpuId, ino: u64) {
+        let ivec = delta
+            .cpus
+            .get_mut(&cpu_id)
+            .unwrap()
+            .ivecs
+            .get_mut(&format!("/pci@{},0 {}", cpu_id, ino))
+            .unwrap();
+        ivec.pinned = true;
+    }
+
+    #[test]
+    fn test_pin_rules() {
+        let config = Config::parse(
+            "[[pin]]\nname = \"igb#1\"\n\n[[pin]]\nbuspath = \"/pci@1,0\"\nino = 7\n",
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let delta = delta(vec![delta_cpu(1000, &[10, 10, 10])]);
+        let mut ivec = delta.cpus[&0].ivecs["/pci@0,0 0"].clone();
+        assert!(!config.is_pinned(&ivec));
+
+        // Any handler of a shared ivec pins the lot
+        ivec.name = "e1000g#0/igb#1".to_string();
+        assert!(config.is_pinned(&ivec));
+
+        ivec.name = "igb#10".to_string();
+        ivec.buspath = "/pci@1,0".to_string();
+        assert!(!config.is_pinned(&ivec));
+        ivec.ino = 7;
+        assert!(config.is_pinned(&ivec));
+
+        let config = Config::parse("[[pin]]\n").unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_do_reconfig_respects_pins() {
+        let mut delta = two_cpu_skewed_delta();
+        pin(&mut delta, 0, 1);
+
+        let plan = do_reconfig(&delta, &Config::default()).unwrap().unwrap();
+        assert!(!plan.moves.is_empty());
+        assert!(plan.moves.iter().all(|mv| mv.inum != "/pci@0,0 1"));
+        assert_eq!(
+            vec![Pinned {
+                inum: "/pci@0,0 1".to_string(),
+                name: "drv#1".to_string(),
+                cpu: 0,
+            }],
+            plan.pinned
+        );
+
+        // With nothing left to move, the imbalance is none of our business
+        pin(&mut delta, 0, 0);
+        pin(&mut delta, 0, 2);
+        assert_eq!(0.0, goodness(&delta, &Config::default()));
+        assert!(do_reconfig(&delta, &Config::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_do_reconfig_evacuates_excluded_cpus() {
+        let mut delta = delta(vec![
+            delta_cpu(1000, &[100]),
+            delta_cpu(1000, &[100]),
+            delta_cpu(1000, &[300, 200, 70]),
+            delta_cpu(1000, &[50]),
+        ]);
+        pin(&mut delta, 2, 2);
+
+        // Only two cpus are left to take interrupts
+        let config = Config::parse("exclude_cpus = \"2-3\"\n").unwrap();
+        assert_eq!(1.0, goodness(&delta, &config));
+
+        let plan = do_reconfig(&delta, &config).unwrap().unwrap();
+        assert!(plan.moves.iter().all(|mv| mv.to < 2));
+
+        let mut moved: Vec<&str> = plan.moves.iter().map(|mv| mv.inum.as_str()).collect();
+        moved.sort();
+        assert_eq!(vec!["/pci@2,0 0", "/pci@2,0 1", "/pci@3,0 0"], moved);
+
+        // The eligible cpus share the evacuated load between them
+        let mut load: BTreeMap<CpuId, u64> = BTreeMap::from([(0, 100), (1, 100)]);
+        for mv in &plan.moves {
+            *load.get_mut(&mv.to).unwrap() += delta.cpus[&mv.from].ivecs[&mv.inum].time;
+        }
+        assert_eq!(750, load.values().sum::<u64>());
+        assert!(load.values().all(|nsec| nsec.abs_diff(375) <= 100));
+
+        let everything = Config::parse("exclude_cpus = \"0-3\"\n").unwrap();
+        assert!(do_reconfig(&delta, &everything).is_err());
+
+        // Once only pinned interrupts remain, an excluded cpu doesn't count against us
+        pin(&mut delta, 2, 0);
+        pin(&mut delta, 2, 1);
+        pin(&mut delta, 3, 0);
+        assert_eq!(0.0, goodness(&delta, &config));
+    }
+
+    #[test]
+    fn test_any_eligible() {
+        let states = BTreeMap::from([
+            (0, CpuState::OnLine),
+            (1, CpuState::OnLine),
+            (2, CpuState::NoIntr),
+            (3, CpuState::OffLine),
+        ]);
+
+        assert!(any_eligible(&states, &Config::default()));
+        let config = Config::parse("exclude_cpus = \"0\"\n").unwrap();
+        assert!(any_eligible(&states, &config));
+
+        // Excluding only the cpus which are on-line leaves nothing, whatever the rest are doing
+        let config = Config::parse("exclude_cpus = \"0-1\"\n").unwrap();
+        assert!(!any_eligible(&states, &config));
+    }
+
+    #[test]
+    fn test_cpu_state() {
+        for state in [
+            "on-line",
+            "no-intr",
+            "off-line",
+            "spare",
+            "faulted",
+            "powered-off",
+            "p-online",
+        ] {
+            assert_eq!(state, CpuState::parse(state).to_string());
+        }
+
+        assert_eq!(CpuState::NoIntr, CpuState::parse("no-intr"));
+        assert!(CpuState::parse("on-line").takes_interrupts());
+        assert!(!CpuState::parse("no-intr").takes_interrupts());
+        assert!(!CpuState::parse("p-online").takes_interrupts());
+    }
+
+    #[test]
+    fn test_generate_delta_state_change() {
+        let stat = |snaptime: u64, state: CpuState| GotStat {
+            snaptime,
+            cpus: BTreeMap::from([
+                (
+                    0,
+                    CpuStat {
+                        state: CpuState::OnLine,
+                        tot: snaptime,
+                        crtime: 0,
+                        ivecs: BTreeMap::new(),
+                    },
+                ),
+                (
+                    1,
+                    CpuStat {
+                        state,
+                        tot: snaptime,
+                        crtime: 0,
+                        ivecs: BTreeMap::new(),
+                    },
+                ),
+            ]),
+        };
+
+        let delta = generate_delta(&stat(1000, CpuState::NoIntr), &stat(2000, CpuState::NoIntr));
+        assert!(!delta.missing);
+        assert_eq!(CpuState::NoIntr, delta.cpus[&1].state);
+
+        let delta = generate_delta(&stat(1000, CpuState::OnLine), &stat(2000, CpuState::NoIntr));
+        assert!(delta.missing);
+    }
+
+    #[test]
+    fn test_do_reconfig_evacuates_ineligible_cpus() {
+        let mut delta = delta(vec![
+            delta_cpu(1000, &[100]),
+            delta_cpu(1000, &[100]),
+            delta_cpu(1000, &[300, 20]),
+            delta_cpu(1000, &[50]),
+        ]);
+        delta.cpus.get_mut(&2).unwrap().state = CpuState::NoIntr;
+        delta.cpus.get_mut(&3).unwrap().state = CpuState::Faulted;
+        pin(&mut delta, 2, 1);
+
+        let config = Config::default();
+        assert_eq!(1.0, goodness(&delta, &config));
+
+        let plan = do_reconfig(&delta, &config).unwrap().unwrap();
+        assert!(plan.moves.iter().all(|mv| mv.to < 2));
+
+        let moved: Vec<&str> = plan.moves.iter().map(|mv| mv.inum.as_str()).collect();
+        assert!(moved.contains(&"/pci@2,0 0") && moved.contains(&"/pci@3,0 0"));
+        assert!(!moved.contains(&"/pci@2,0 1"));
+        assert_eq!(2, plan.pinned[0].cpu);
+
+        // Excluding one of the two on-line cpus leaves a single one to take everything
+        let config = Config::parse("exclude_cpus = \"1\"\n").unwrap();
+        let plan = do_reconfig(&delta, &config).unwrap().unwrap();
+        assert!(plan.moves.iter().all(|mv| mv.to == 0));
+        assert_eq!(3, plan.moves.len());
     }
 
-    Ok(())
+    #[test]
+    fn test_do_reconfig_evacuates_without_improving() {
+        // Both eligible cpus are saturated by pinned interrupts, so goodness is stuck at 1.0
+        // whatever we do, but what's on the excluded cpu still has to go.
+        let mut delta = delta(vec![
+            delta_cpu(1000, &[900, 60]),
+            delta_cpu(1000, &[900, 60]),
+            delta_cpu(1000, &[20]),
+        ]);
+        pin(&mut delta, 0, 0);
+        pin(&mut delta, 1, 0);
+
+        let config = Config::parse("exclude_cpus = \"2\"\n").unwrap();
+        let plan = do_reconfig(&delta, &config).unwrap().unwrap();
+        assert_eq!(1.0, plan.newgoodness);
+        assert!(plan
+            .moves
+            .iter()
+            .any(|mv| mv.inum == "/pci@2,0 0" && mv.to < 2));
+    }
+
+    #[test]
+    fn test_consolidate_msi_groups() {
+        let mut cpus = BTreeMap::from([(0, cpu_stat(1000, &[100, 50, 30, 10]))]);
+        cpus.get_mut(&0)
+            .unwrap()
+            .ivecs
+            .get_mut("/pci@0,0 1")
+            .unwrap()
+            .crtime = 5;
+
+        // Three MSI vectors of one device, plus an unrelated ivec
+        let msidevs: MsiDevs = BTreeMap::from([(
+            "igb#0".to_string(),
+            (0..3)
+                .map(|ino| (ino, (0, format!("/pci@0,0 {}", ino))))
+                .collect(),
+        )]);
+        consolidate_msi_groups(&mut cpus, &msidevs);
+
+        let ivecs = &cpus[&0].ivecs;
+        assert_eq!(3, ivecs["/pci@0,0 0"].num_ino);
+        assert_eq!(180, ivecs["/pci@0,0 0"].time);
+        assert_eq!(5, ivecs["/pci@0,0 0"].crtime);
+        assert_eq!(0, ivecs["/pci@0,0 1"].num_ino);
+        assert_eq!(0, ivecs["/pci@0,0 2"].num_ino);
+        assert_eq!(1, ivecs["/pci@0,0 3"].num_ino);
+        assert_eq!(10, ivecs["/pci@0,0 3"].time);
+        assert!(!ivecs["/pci@0,0 0"].pinned);
+    }
+
+    #[test]
+    fn test_consolidate_msi_groups_pinned() {
+        let mut cpus = BTreeMap::from([(0, cpu_stat(1000, &[100, 50, 30, 10]))]);
+        let msidevs: MsiDevs = BTreeMap::from([(
+            "igb#0".to_string(),
+            (0..3)
+                .map(|ino| (ino, (0, format!("/pci@0,0 {}", ino))))
+                .collect(),
+        )]);
+
+        // Pinning one vector which isn't the lowest pins the whole group, which moves together
+        let config = Config::parse("[[pin]]\nbuspath = \"/pci@0,0\"\nino = 2\n").unwrap();
+        apply_pin_rules(&mut cpus, &config);
+        consolidate_msi_groups(&mut cpus, &msidevs);
+
+        let ivecs = &cpus[&0].ivecs;
+        assert_eq!(3, ivecs["/pci@0,0 0"].num_ino);
+        assert!(ivecs["/pci@0,0 0"].pinned);
+        assert!(!ivecs["/pci@0,0 3"].pinned);
+    }
+
+    fn got_stat(snaptime: u64, cpus: Vec<CpuStat>) -> GotStat {
+        GotStat {
+            snaptime,
+            cpus: cpus
+                .into_iter()
+                .enumerate()
+                .map(|(cpu_id, cpu)| (cpu_id as CpuId, cpu))
+                .collect(),
+        }
+    }
+
+    // Two samples, a second apart, which generate_delta() is happy with
+    fn stat_pair() -> (GotStat, GotStat) {
+        (
+            got_stat(
+                1000,
+                vec![cpu_stat(10_000, &[100, 50]), cpu_stat(10_000, &[20])],
+            ),
+            got_stat(
+                2000,
+                vec![cpu_stat(20_000, &[300, 80]), cpu_stat(20_000, &[20])],
+            ),
+        )
+    }
+
+    #[test]
+    fn test_generate_delta() {
+        let (stat, newstat) = stat_pair();
+        let delta = generate_delta(&stat, &newstat);
+
+        assert!(!delta.missing);
+        assert_eq!((1000, 2000), (delta.minsnap, delta.maxsnap));
+        assert_eq!(230, delta.cpus[&0].intrs);
+        assert_eq!(200, delta.cpus[&0].bigintr);
+        assert_eq!(10_000, delta.cpus[&0].tot);
+        assert_eq!(30, delta.cpus[&0].ivecs["/pci@0,0 1"].time);
+        assert_eq!(0, delta.cpus[&1].intrs);
+        assert_close(0.0115, delta.avgintrload, "avgintrload");
+        assert_close(115.0, delta.avgintrnsec, "avgintrnsec");
+
+        // Snapshots out of order can't be compared
+        assert!(generate_delta(&newstat, &stat).missing);
+    }
+
+    #[test]
+    fn test_generate_delta_cpu_count_changed() {
+        let (stat, mut newstat) = stat_pair();
+        newstat.cpus.insert(2, cpu_stat(20_000, &[]));
+        assert!(generate_delta(&stat, &newstat).missing);
+
+        // The same number of cpus, but not the same ones
+        let (stat, mut newstat) = stat_pair();
+        let cpu = newstat.cpus.remove(&1).unwrap();
+        newstat.cpus.insert(3, cpu);
+        assert!(generate_delta(&stat, &newstat).missing);
+    }
+
+    #[test]
+    fn test_generate_delta_crtime_changed() {
+        let (stat, mut newstat) = stat_pair();
+        newstat.cpus.get_mut(&1).unwrap().crtime = 1500;
+        assert!(generate_delta(&stat, &newstat).missing);
+
+        let (stat, mut newstat) = stat_pair();
+        let cpu = newstat.cpus.get_mut(&0).unwrap();
+        cpu.ivecs.get_mut("/pci@0,0 0").unwrap().crtime = 1500;
+        assert!(generate_delta(&stat, &newstat).missing);
+    }
+
+    #[test]
+    fn test_generate_delta_ivec_count_changed() {
+        let (stat, mut newstat) = stat_pair();
+        newstat.cpus.insert(1, cpu_stat(20_000, &[20, 10]));
+        assert!(generate_delta(&stat, &newstat).missing);
+
+        // An ivec moved from one cpu to the other
+        let (stat, mut newstat) = stat_pair();
+        let ivec = newstat
+            .cpus
+            .get_mut(&0)
+            .unwrap()
+            .ivecs
+            .remove("/pci@0,0 1")
+            .unwrap();
+        newstat
+            .cpus
+            .get_mut(&1)
+            .unwrap()
+            .ivecs
+            .insert("/pci@0,0 1".to_string(), ivec);
+        assert!(generate_delta(&stat, &newstat).missing);
+    }
+
+    #[test]
+    fn test_generate_delta_counters_went_backwards() {
+        let (stat, mut newstat) = stat_pair();
+        newstat.cpus.get_mut(&0).unwrap().tot = 5_000;
+        assert!(generate_delta(&stat, &newstat).missing);
+
+        let (stat, mut newstat) = stat_pair();
+        let cpu = newstat.cpus.get_mut(&0).unwrap();
+        cpu.ivecs.get_mut("/pci@0,0 1").unwrap().time = 10;
+        assert!(generate_delta(&stat, &newstat).missing);
+    }
+
+    // The ith of a run of back-to-back deltas, each secs long
+    fn window_delta(i: u64, secs: u64) -> Delta {
+        let span = secs * NANOSEC as u64;
+
+        Delta {
+            minsnap: i * span,
+            maxsnap: (i + 1) * span,
+            ..two_cpu_skewed_delta()
+        }
+    }
+
+    #[test]
+    fn test_deltas_window() {
+        let mut deltas = Deltas::new(60);
+
+        // Nothing to decide on until a full window is in
+        for i in 0..5 {
+            assert!(!deltas.push(window_delta(i, 10)));
+            assert!(!deltas.is_full());
+        }
+
+        assert!(deltas.push(window_delta(5, 10)));
+        assert!(deltas.is_full());
+        assert_eq!(6, deltas.deltas.len());
+
+        // After that, the oldest delta makes way for each new one, and we only decide once
+        assert!(!deltas.push(window_delta(6, 10)));
+        assert!(deltas.is_full());
+        assert_eq!(6, deltas.deltas.len());
+        assert_eq!(window_delta(1, 10).minsnap, deltas.deltas[0].minsnap);
+        assert_close(60.0, deltas.tottime, "tottime");
+
+        // Unless dropping it would leave us short
+        assert!(!deltas.push(window_delta(7, 5)));
+        assert_eq!(7, deltas.deltas.len());
+
+        deltas.clear();
+        assert!(!deltas.is_full());
+        assert!(deltas.deltas.is_empty());
+    }
+
+    #[test]
+    fn test_stat_failed() {
+        let mut sleep_time = SleepTime::new(&Config::default());
+        let mut deltas = Deltas::new(60);
+        let mut stat = Some(stat_pair().0);
+
+        for i in 0..3 {
+            deltas.push(window_delta(i, 10));
+        }
+
+        // A slow snapshot is worth waiting out
+        let slow = StatError::IncoherentTimerange {
+            span: 1.0,
+            timerange: 0.1,
+            limit: 0.01,
+        };
+        stat_failed(&slow, &mut stat, &mut deltas, &mut sleep_time);
+        assert!(stat.is_some());
+        assert_eq!(3, deltas.deltas.len());
+
+        // But once the kstats have changed under us, the window goes along with the stats
+        stat_failed(
+            &StatError::MissingCpuSys(1),
+            &mut stat,
+            &mut deltas,
+            &mut sleep_time,
+        );
+        assert!(stat.is_none());
+        assert!(deltas.deltas.is_empty());
+        assert_close(0.0, deltas.tottime, "tottime");
+
+        stat = Some(stat_pair().0);
+        deltas.push(window_delta(3, 10));
+        stat_failed(
+            &StatError::SingleCpu,
+            &mut stat,
+            &mut deltas,
+            &mut sleep_time,
+        );
+        assert_eq!(SleepState::SingleCpu, sleep_time.state());
+        assert!(stat.is_none());
+        assert!(deltas.deltas.is_empty());
+    }
+
+    #[test]
+    fn test_compress_deltas() {
+        assert!(compress_deltas(&[]).is_none());
+
+        let deltas: Vec<Delta> = (0..3).map(|i| window_delta(i, 10)).collect();
+        let compdelta = compress_deltas(&deltas).unwrap();
+
+        assert!(!compdelta.missing);
+        assert_eq!(deltas[0].minsnap, compdelta.minsnap);
+        assert_eq!(deltas[2].maxsnap, compdelta.maxsnap);
+        assert_eq!(1800, compdelta.cpus[&0].intrs);
+        assert_eq!(3000, compdelta.cpus[&0].tot);
+        assert_eq!(900, compdelta.cpus[&0].bigintr);
+        assert_eq!(600, compdelta.cpus[&0].ivecs["/pci@0,0 1"].time);
+        assert_close(0.6, compdelta.cpus[&0].intrload, "intrload");
+        assert_eq!(0, compdelta.cpus[&1].intrs);
+        assert_close(0.3, compdelta.avgintrload, "avgintrload");
+        assert_close(900.0, compdelta.avgintrnsec, "avgintrnsec");
+
+        // One bad delta spoils the window
+        let mut deltas = deltas;
+        deltas[1] = Delta::missing(deltas[1].minsnap, deltas[1].maxsnap);
+        assert!(compress_deltas(&deltas).is_none());
+    }
+
+    #[test]
+    fn test_stuck_evacuation() {
+        let mut delta = delta(vec![
+            delta_cpu(1000, &[100]),
+            delta_cpu(1000, &[100]),
+            delta_cpu(1000, &[300]),
+        ]);
+        delta.cpus.get_mut(&2).unwrap().state = CpuState::NoIntr;
+
+        let config = Config::default();
+        let plan = do_reconfig(&delta, &config).unwrap().unwrap();
+        let mut actuator = recorder_for(&delta);
+        actuator.refuse("/pci@2,0", 0);
+
+        // The kernel won't let go of it
+        let failed = apply_plan(&plan, &mut actuator, &mut OriginalBindings::default());
+        assert_eq!(1, failed.len());
+        assert_eq!(Some(2), actuator.cpu_of("/pci@2,0", 0));
+
+        let mut stuck = Stuck::default();
+        stuck.failed(&failed[0], &CpuState::NoIntr);
+
+        // So from the next sample on, it's pinned where it is, and no longer worth replanning
+        let mut stat = stat_of(&delta);
+        stuck.pin(&mut stat);
+        assert!(stat.cpus[&2].ivecs["/pci@2,0 0"].pinned);
+
+        pin(&mut delta, 2, 0);
+        assert!(goodness(&delta, &config) < config.goodness_mindelta);
+        assert!(do_reconfig(&delta, &config).unwrap().is_none());
+
+        // Until its cpu comes back on-line, when it's just another ivec again
+        let mut stat = stat_of(&delta);
+        stat.cpus.get_mut(&2).unwrap().state = CpuState::OnLine;
+        stat.cpus
+            .get_mut(&2)
+            .unwrap()
+            .ivecs
+            .get_mut("/pci@2,0 0")
+            .unwrap()
+            .pinned = false;
+        stuck.pin(&mut stat);
+        assert!(!stat.cpus[&2].ivecs["/pci@2,0 0"].pinned);
+
+        stat.cpus.get_mut(&2).unwrap().state = CpuState::NoIntr;
+        stuck.pin(&mut stat);
+        assert!(!stat.cpus[&2].ivecs["/pci@2,0 0"].pinned);
+    }
 }
@@ -0,0 +1,110 @@
+use crate::util::intr_binding::{self, IntrMoveError};
+use crate::util::is_apic;
+use crate::util::types::Move;
+use log::info;
+
+#[cfg(test)]
+use crate::util::types::CpuId;
+#[cfg(test)]
+use std::collections::{BTreeMap, BTreeSet};
+
+// Everything intrd does to, or asks of, the interrupt controller goes through one of these, so
+// the control path can run without the real thing.
+pub trait Actuator {
+    fn move_interrupt(&mut self, mv: &Move) -> Result<(), IntrMoveError>;
+
+    // Whether a move_interrupt() which succeeds has really changed the binding
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    // Whether the bus at buspath is hosted by a pcplusmp APIC or APIX
+    fn is_apic(&self, buspath: &str) -> anyhow::Result<bool>;
+}
+
+// Talks to the nexus driver with pcitool ioctls
+pub struct LiveActuator;
+
+impl Actuator for LiveActuator {
+    fn move_interrupt(&mut self, mv: &Move) -> Result<(), IntrMoveError> {
+        intr_binding::move_interrupt(&mv.buspath, mv.from, mv.ino, mv.to, mv.num_ino)
+    }
+
+    fn is_apic(&self, buspath: &str) -> anyhow::Result<bool> {
+        is_apic::is_apic(buspath)
+    }
+}
+
+// Says what it would have moved, but leaves the bindings alone. Asking about the controller is
+// harmless, so that still goes to the real thing.
+pub struct DryRunActuator;
+
+impl Actuator for DryRunActuator {
+    fn move_interrupt(&mut self, mv: &Move) -> Result<(), IntrMoveError> {
+        info!("dry run: would move {}", mv);
+        Ok(())
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    fn is_apic(&self, buspath: &str) -> anyhow::Result<bool> {
+        is_apic::is_apic(buspath)
+    }
+}
+
+// Keeps an in-memory binding table of (buspath, ino) -> cpu, and behaves like the kernel does
+// when asked to move an interrupt from a CPU it isn't on, or one it won't let go of.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct RecordingActuator {
+    pub apic: bool,
+    pub bindings: BTreeMap<(String, u64), CpuId>,
+    pub moves: Vec<Move>,
+    pub refused: BTreeSet<(String, u64)>, // interrupts which are busy whenever we try to move them
+}
+
+#[cfg(test)]
+impl RecordingActuator {
+    pub fn bind(&mut self, buspath: &str, ino: u64, cpu: CpuId) {
+        self.bindings.insert((buspath.to_string(), ino), cpu);
+    }
+
+    pub fn refuse(&mut self, buspath: &str, ino: u64) {
+        self.refused.insert((buspath.to_string(), ino));
+    }
+
+    pub fn cpu_of(&self, buspath: &str, ino: u64) -> Option<CpuId> {
+        self.bindings.get(&(buspath.to_string(), ino)).copied()
+    }
+}
+
+#[cfg(test)]
+impl Actuator for RecordingActuator {
+    fn move_interrupt(&mut self, mv: &Move) -> Result<(), IntrMoveError> {
+        if self.refused.contains(&(mv.buspath.clone(), mv.ino)) {
+            return Err(IntrMoveError::Busy { ino: mv.ino });
+        }
+
+        match self.bindings.get_mut(&(mv.buspath.clone(), mv.ino)) {
+            Some(cpu) if *cpu == mv.from => {
+                *cpu = mv.to;
+                self.moves.push(mv.clone());
+                Ok(())
+            }
+            Some(_) => Err(IntrMoveError::InvalidArgument {
+                ino: mv.ino,
+                cpu: mv.to,
+            }),
+            None => Err(IntrMoveError::NoSuchDevice {
+                ino: mv.ino,
+                cpu: mv.to,
+            }),
+        }
+    }
+
+    fn is_apic(&self, _buspath: &str) -> anyhow::Result<bool> {
+        Ok(self.apic)
+    }
+}
@@ -0,0 +1,180 @@
+use crate::util::config::Config;
+use crate::util::constants::{CONFIG_PATH, SYSLOG_PROCESS_NAME};
+use crate::util::errors::{ConfigError, UsageError};
+use log::LevelFilter;
+use std::path::{Path, PathBuf};
+
+pub fn usage() -> String {
+    format!(
+        "Usage: {} [options]
+
+Options:
+  -D, --debug            log everything intrd is thinking; twice to include find_goal()'s search
+  -S, --scenario FILE    replay kstats from a scenario FILE in place of the kernel's
+                         (not yet supported, and refused)
+  -n, --dry-run          plan interrupt moves and log them, but never make them
+  -r, --restore          put back the bindings we changed on SIGINT or SIGTERM
+  -i, --interval SECS    time to sleep between samples, overriding normal_sleep_time
+  -w, --window SECS      length of the window of samples averaged before deciding,
+                         overriding stats_len
+  -c, --config FILE      read settings from FILE (default {}, if it exists)
+  -o, --one-shot         evaluate one full window, reconfigure if that's called for, and exit
+  -h, --help             show this message and exit",
+        SYSLOG_PROCESS_NAME, CONFIG_PATH
+    )
+}
+
+// Options taken from the command line
+#[derive(Debug, Default)]
+pub struct Args {
+    pub debug: u8, // -D: log everything intrd is thinking. Twice, and log find_goal()'s search too
+    pub dry_run: bool, // -n: plan interrupt moves, but never make them
+    pub restore: bool, // -r: put back the bindings we changed when told to exit
+    pub interval: Option<u64>, // -i: seconds to sleep between samples, when not idle
+    pub window: Option<u64>, // -w: seconds of samples to average before deciding anything
+    pub config: Option<PathBuf>, // -c: settings file
+    pub one_shot: bool, // -o: exit after the first full window has been evaluated
+    pub help: bool, // -h: print usage and exit
+}
+
+impl Args {
+    // Parse arguments, so that service manifests can say how intrd should run. -D generates a
+    // significant amount of syslog output. The original's -S, which fed intrd a kstat
+    // "simulator" script in place of the kernel's kstats, has no equivalent here yet, so it's
+    // refused rather than ignored, and the usage message says as much. Options which take a
+    // value accept it as the next argument, or after '=' on the long form.
+    pub fn parse<I: IntoIterator<Item = String>>(argv: I) -> Result<Self, UsageError> {
+        let mut args = Self::default();
+        let mut argv = argv.into_iter();
+
+        while let Some(arg) = argv.next() {
+            let (opt, inline) = match arg.split_once('=') {
+                Some((opt, value)) if opt.starts_with("--") => (opt, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let flag = || match inline {
+                Some(_) => Err(UsageError::UnexpectedValue(opt.to_string())),
+                None => Ok(true),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| argv.next())
+                    .ok_or_else(|| UsageError::MissingValue(opt.to_string()))
+            };
+
+            match opt {
+                "-D" | "--debug" => args.debug = args.debug.saturating_add(flag()? as u8),
+                "-S" | "--scenario" => return Err(UsageError::Unsupported(opt.to_string())),
+                "-n" | "--dry-run" => args.dry_run = flag()?,
+                "-r" | "--restore" => args.restore = flag()?,
+                "-i" | "--interval" => args.interval = Some(seconds(opt, &value()?)?),
+                "-w" | "--window" => args.window = Some(seconds(opt, &value()?)?),
+                "-c" | "--config" => args.config = Some(PathBuf::from(value()?)),
+                "-o" | "--one-shot" => args.one_shot = flag()?,
+                "-h" | "--help" => args.help = flag()?,
+                _ => return Err(UsageError::UnknownOption(arg.clone())),
+            }
+        }
+
+        Ok(args)
+    }
+
+    // The config file we were told to use, or else the default one if there is one, with any
+    // thresholds given on the command line laid on top.
+    pub fn config(&self) -> Result<Config, ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path, true)?,
+            None => Config::load(Path::new(CONFIG_PATH), false)?,
+        };
+
+        if let Some(interval) = self.interval {
+            config.normal_sleep_time = interval;
+        }
+
+        if let Some(window) = self.window {
+            config.stats_len = window;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        match self.debug {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+}
+
+// A whole, non-zero number of seconds
+fn seconds(opt: &str, value: &str) -> Result<u64, UsageError> {
+    match value.parse() {
+        Ok(secs) if secs > 0 => Ok(secs),
+        _ => Err(UsageError::BadValue {
+            opt: opt.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> Result<Args, UsageError> {
+        Args::parse(argv.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(LevelFilter::Info, args.log_level());
+        assert_eq!(None, args.interval);
+        assert_eq!(None, args.window);
+        assert!(!args.dry_run && !args.one_shot && args.config.is_none());
+
+        let args = parse(&[
+            "-D",
+            "--debug",
+            "-n",
+            "-i",
+            "5",
+            "--window=120",
+            "--config",
+            "/etc/intrd-rs.toml",
+            "-o",
+        ])
+        .unwrap();
+        assert_eq!(LevelFilter::Trace, args.log_level());
+        assert!(args.dry_run && args.one_shot);
+        assert_eq!(Some(5), args.interval);
+        assert_eq!(Some(120), args.window);
+        assert_eq!(Some("/etc/intrd-rs.toml".into()), args.config);
+
+        assert!(parse(&["-h"]).unwrap().help);
+    }
+
+    #[test]
+    fn test_parse_bad_args() {
+        for argv in [
+            &["-x"][..],
+            &["extra"],
+            &["-i"],
+            &["-i", "0"],
+            &["--interval=soon"],
+            &["--dry-run=yes"],
+            &["-S", "scenario.pl"],
+        ] {
+            assert!(parse(argv).is_err(), "{:?} should be rejected", argv);
+        }
+
+        assert!(matches!(
+            parse(&["--scenario=scenario.pl"]),
+            Err(UsageError::Unsupported(_))
+        ));
+        assert!(usage().contains("--scenario FILE"));
+    }
+}
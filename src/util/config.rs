@@ -0,0 +1,364 @@
+use crate::util::constants::{
+    FIND_GOAL_MAX_NODES, GOODNESS_MINDELTA, GOODNESS_PATHOLOGICAL, GOODNESS_UNSAFE_LOAD,
+    IDLE_INTR_LOAD, IDLE_SLEEP_TIME, NORMAL_SLEEP_TIME, SINGLE_CPU_SLEEP_TIME, STATS_LEN,
+    TIME_RANGE_TOO_HIGH,
+};
+use crate::util::errors::ConfigError;
+use crate::util::types::{CpuId, Ivec};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+// The tuning thresholds, which can be set in a TOML file. Anything the file doesn't mention
+// keeps the value the original intrd hard-coded.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub normal_sleep_time: u64,     // time to sleep between samples
+    pub idle_sleep_time: u64,       // time to sleep when idle
+    pub single_cpu_sleep_time: u64, // used only on single CPU systems
+    pub idle_intr_load: f64,        // interrupt load below which we're idle
+    pub stats_len: u64,             // time period (in secs) to keep in deltas
+    pub time_range_too_high: f64,   // largest tolerable spread of kstat snaptimes
+    pub goodness_unsafe_load: f64,  // interrupt load above which a CPU is overloaded
+    pub goodness_mindelta: f64,     // change in goodness worth acting on
+    pub goodness_pathological: f64, // goodness above which we always reconfigure
+    pub find_goal_max_nodes: usize, // nodes find_goal() may search per reconfiguration
+    pub pin: Vec<PinRule>,          // ivecs which must never be moved
+    pub exclude_cpus: CpuSet,       // cpus which must never be given interrupts
+}
+
+// A set of cpu ids, written as a list of ids and ranges, such as "2,8-15"
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CpuSet(BTreeSet<CpuId>);
+
+impl CpuSet {
+    pub fn contains(&self, cpu_id: CpuId) -> bool {
+        self.0.contains(&cpu_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for CpuSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cpu_id = |id: &str| {
+            id.trim()
+                .parse::<CpuId>()
+                .ok()
+                .filter(|id| *id >= 0)
+                .ok_or_else(|| format!("'{}' is not a cpu id", id.trim()))
+        };
+        let mut cpus = BTreeSet::new();
+
+        for item in s.split(',').filter(|item| !item.trim().is_empty()) {
+            match item.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (cpu_id(first)?, cpu_id(last)?);
+
+                    if first > last {
+                        return Err(format!("'{}' is not a range of cpus", item.trim()));
+                    }
+
+                    cpus.extend(first..=last);
+                }
+                None => {
+                    cpus.insert(cpu_id(item)?);
+                }
+            }
+        }
+
+        Ok(Self(cpus))
+    }
+}
+
+impl TryFrom<String> for CpuSet {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+// Collapses runs of ids back into ranges
+impl fmt::Display for CpuSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ranges: Vec<(CpuId, CpuId)> = Vec::new();
+
+        for &cpu_id in &self.0 {
+            match ranges.last_mut() {
+                Some((_, last)) if *last + 1 == cpu_id => *last = cpu_id,
+                _ => ranges.push((cpu_id, cpu_id)),
+            }
+        }
+
+        let ranges: Vec<String> = ranges
+            .iter()
+            .map(|&(first, last)| match first == last {
+                true => first.to_string(),
+                false => format!("{}-{}", first, last),
+            })
+            .collect();
+
+        write!(f, "{}", ranges.join(","))
+    }
+}
+
+// Picks out interrupts which must stay on whatever CPU they're on, for instance because a
+// latency-critical device has been bound there by hand. Every field given must match.
+//
+//     [[pin]]
+//     name = "igb#0"
+//
+//     [[pin]]
+//     buspath = "/pci@0,0/pci8086,2f08@3"
+//     ino = 38
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PinRule {
+    pub name: Option<String>, // driver#instance, matching any of the handlers sharing an ivec
+    pub buspath: Option<String>,
+    pub ino: Option<u64>,
+}
+
+impl PinRule {
+    pub fn matches(&self, ivec: &Ivec) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|name| ivec.handlers().contains(&name.as_str()))
+            && self
+                .buspath
+                .as_ref()
+                .is_none_or(|buspath| *buspath == ivec.buspath)
+            && self.ino.is_none_or(|ino| ino == ivec.ino)
+    }
+}
+
+impl fmt::Display for PinRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = Vec::new();
+
+        if let Some(name) = &self.name {
+            fields.push(format!("name={}", name));
+        }
+        if let Some(buspath) = &self.buspath {
+            fields.push(format!("buspath={}", buspath));
+        }
+        if let Some(ino) = self.ino {
+            fields.push(format!("ino={}", ino));
+        }
+
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            normal_sleep_time: NORMAL_SLEEP_TIME,
+            idle_sleep_time: IDLE_SLEEP_TIME,
+            single_cpu_sleep_time: SINGLE_CPU_SLEEP_TIME,
+            idle_intr_load: IDLE_INTR_LOAD,
+            stats_len: STATS_LEN,
+            time_range_too_high: TIME_RANGE_TOO_HIGH,
+            goodness_unsafe_load: GOODNESS_UNSAFE_LOAD,
+            goodness_mindelta: GOODNESS_MINDELTA,
+            goodness_pathological: GOODNESS_PATHOLOGICAL,
+            find_goal_max_nodes: FIND_GOAL_MAX_NODES,
+            pin: Vec::new(),
+            exclude_cpus: CpuSet::default(),
+        }
+    }
+}
+
+impl Config {
+    // Read the config at path. If required is false, a file which isn't there just means the
+    // defaults.
+    pub fn load(path: &Path, required: bool) -> Result<Self, ConfigError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default())
+            }
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.display().to_string(),
+                    source,
+                })
+            }
+        };
+
+        Self::parse(&text).map_err(|source| ConfigError::Parse {
+            path: path.display().to_string(),
+            source: Box::new(source),
+        })
+    }
+
+    pub fn is_pinned(&self, ivec: &Ivec) -> bool {
+        self.pin.iter().any(|rule| rule.matches(ivec))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    // Catch values which would make intrd misbehave, rather than just behave differently
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid =
+            |field: &'static str, reason: String| Err(ConfigError::Invalid { field, reason });
+        let fraction = |field: &'static str, value: f64| {
+            // Written this way round so that NaN fails too
+            if value > 0.0 && value <= 1.0 {
+                Ok(())
+            } else {
+                invalid(field, format!("{} is not in (0, 1]", value))
+            }
+        };
+
+        for (field, secs) in [
+            ("normal_sleep_time", self.normal_sleep_time),
+            ("idle_sleep_time", self.idle_sleep_time),
+            ("single_cpu_sleep_time", self.single_cpu_sleep_time),
+        ] {
+            if secs == 0 {
+                return invalid(field, "must be at least 1 second".to_string());
+            }
+        }
+
+        // Deltas longer than the window are thrown away. If we sleep for longer than that, we'll
+        // never reconfigure, and never notice that we've stopped being idle.
+        let longest = self.normal_sleep_time.max(self.idle_sleep_time);
+
+        if self.stats_len < longest {
+            return invalid(
+                "stats_len",
+                format!(
+                    "{}s is shorter than the longest sleep time of {}s",
+                    self.stats_len, longest
+                ),
+            );
+        }
+
+        fraction("idle_intr_load", self.idle_intr_load)?;
+        fraction("time_range_too_high", self.time_range_too_high)?;
+        fraction("goodness_unsafe_load", self.goodness_unsafe_load)?;
+        fraction("goodness_mindelta", self.goodness_mindelta)?;
+        fraction("goodness_pathological", self.goodness_pathological)?;
+
+        if self.find_goal_max_nodes == 0 {
+            return invalid("find_goal_max_nodes", "must be at least 1".to_string());
+        }
+
+        // An empty rule would pin everything, which is a roundabout way of not running intrd
+        if let Some(idx) = self
+            .pin
+            .iter()
+            .position(|rule| rule.name.is_none() && rule.buspath.is_none() && rule.ino.is_none())
+        {
+            return invalid("pin", format!("rule {} has nothing to match on", idx + 1));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "normal_sleep_time={} idle_sleep_time={} single_cpu_sleep_time={} \
+             idle_intr_load={} stats_len={} time_range_too_high={} goodness_unsafe_load={} \
+             goodness_mindelta={} goodness_pathological={} find_goal_max_nodes={}",
+            self.normal_sleep_time,
+            self.idle_sleep_time,
+            self.single_cpu_sleep_time,
+            self.idle_intr_load,
+            self.stats_len,
+            self.time_range_too_high,
+            self.goodness_unsafe_load,
+            self.goodness_mindelta,
+            self.goodness_pathological,
+            self.find_goal_max_nodes
+        )?;
+
+        for rule in &self.pin {
+            write!(f, " pin=[{}]", rule)?;
+        }
+
+        if !self.exclude_cpus.is_empty() {
+            write!(f, " exclude_cpus={}", self.exclude_cpus)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(Config::default(), config);
+        assert_eq!(10, config.normal_sleep_time);
+        assert_eq!(60, config.stats_len);
+        assert_eq!(0.5, config.goodness_pathological);
+        assert!(config.validate().is_ok());
+
+        let config =
+            Config::parse("normal_sleep_time = 5\nstats_len = 120\ngoodness_pathological = 0.75\n")
+                .unwrap();
+        assert_eq!(5, config.normal_sleep_time);
+        assert_eq!(120, config.stats_len);
+        assert_eq!(0.75, config.goodness_pathological);
+        assert_eq!(IDLE_SLEEP_TIME, config.idle_sleep_time);
+        assert!(config.validate().is_ok());
+
+        // Typos shouldn't silently leave the default in place
+        assert!(Config::parse("goodness_min_delta = 0.2").is_err());
+        assert!(Config::parse("stats_len = \"long\"").is_err());
+    }
+
+    #[test]
+    fn test_config_validate() {
+        for text in [
+            "normal_sleep_time = 0",
+            "stats_len = 30",
+            "normal_sleep_time = 90",
+            "idle_intr_load = 1.5",
+            "goodness_mindelta = 0.0",
+            "goodness_unsafe_load = nan",
+            "time_range_too_high = -0.01",
+            "find_goal_max_nodes = 0",
+        ] {
+            let config = Config::parse(text).unwrap();
+            assert!(config.validate().is_err(), "{} should be rejected", text);
+        }
+    }
+
+    #[test]
+    fn test_cpu_set() {
+        let config = Config::parse("exclude_cpus = \"8-10, 2,4,5\"\n").unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!("2,4-5,8-10", config.exclude_cpus.to_string());
+        assert!(config.exclude_cpus.contains(9));
+        assert!(!config.exclude_cpus.contains(3));
+
+        for bad in ["3-1", "x", "-1", "5-", "1-2-3"] {
+            assert!(bad.parse::<CpuSet>().is_err(), "{}", bad);
+        }
+
+        assert!(Config::parse("exclude_cpus = \"2-\"\n").is_err());
+        assert!(Config::default().exclude_cpus.is_empty());
+    }
+}
@@ -6,22 +6,29 @@ pub const PCITOOL_SYSTEM_INTR_INFO: u32 =
 pub const PCITOOL_VERSION: u16 = 2;
 
 // from intr_binding.rs
-//
-// const PCITOOL_VERSION: u32 = 1;
-// const PCITOOL_DEVICE_SET_INTR: i32 = 0x40087002;
-// const PCITOOL_SYSTEM_INTR_INFO: i32 = 0x40087003;
-// const PCITOOL_INTR_FLAG_SET_GROUP: u32 = 1;
-// const PCITOOL_CTLR_TYPE_PCPLUSMP: u32 = 1;
-// const PCITOOL_CTLR_TYPE_APIX: u32 = 2;
+pub const PCITOOL_DEVICE_SET_INTR: u32 =
+    (('P' as u32) << 24) | (('C' as u32) << 16) | (('T' as u32) << 8) | 6;
+pub const PCITOOL_INTR_FLAG_SET_GROUP: u32 = 1;
 
 // from main.rs
 //
 pub const SYSLOG_PROCESS_NAME: &str = "intrd-rs"; // my name
+pub const CONFIG_PATH: &str = "/etc/intrd-rs.toml"; // read if it exists, unless told otherwise
 pub const USING_SCENGEN: bool = false; // I probably won't implement this.
+
+// The thresholds from here on are only defaults. See util::config for overriding them.
 pub const NORMAL_SLEEP_TIME: u64 = 10; // time to sleep between samples
+pub const IDLE_SLEEP_TIME: u64 = 45; // time to sleep when idle
+pub const SINGLE_CPU_SLEEP_TIME: u64 = 60 * 15; // used only on single CPU systems
+pub const IDLE_INTR_LOAD: f64 = 0.1; // idle if interrupt load < 10%
+pub const TIME_RANGE_TOO_HIGH: f64 = 0.01; // max kstat collection time, as a fraction of sleep time
+pub const NANOSEC: f64 = 1_000_000_000.0; // snaptimes and crtimes are in nanoseconds
+pub const STATS_LEN: u64 = 60; // time period (in secs) to keep in deltas
+
+// any goodness over GOODNESS_UNSAFE_LOAD is considered really bad
+// goodness must drop by at least GOODNESS_MINDELTA for a reconfig
+pub const GOODNESS_UNSAFE_LOAD: f64 = 0.9;
+pub const GOODNESS_MINDELTA: f64 = 0.1;
+pub const GOODNESS_PATHOLOGICAL: f64 = 0.50; // always imbalanced above this
 
-// pub const IDLE_SLEEP_TIME: u64 = 45; // time to sleep when idle
-// pub const SINGLE_CPU_SLEEP_TIME: u64 = (60 * 15); // used only on single CPU systems
-// pub const IDLE_INTR_LOAD: f32 = 0.1; // idle if interrupt load < 10%
-// pub const TIME_RANGE_TOO_HIGH: f32 = 0.01;
-// pub const STATS_LEN: usize = 60; // time period (in secs) to keep in deltas
+pub const FIND_GOAL_MAX_NODES: usize = 100_000; // most nodes find_goal() will search per reconfiguration
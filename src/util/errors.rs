@@ -0,0 +1,84 @@
+use crate::util::types::CpuId;
+use thiserror::Error;
+
+// Reasons getstat() can fail to produce a usable snapshot. Some are expected from time to time
+// and the main loop decides how to react to each.
+#[derive(Debug, Error)]
+pub enum StatError {
+    // Nothing to do with one CPU
+    #[error("only one CPU is on-line")]
+    SingleCpu,
+
+    // Gathering the kstats took too long relative to the sleep time for the snapshot to be
+    // treated as a single instant.
+    #[error("kstats took {span:.3}s to gather, timerange {timerange:.4} is over {limit}")]
+    IncoherentTimerange {
+        span: f64,
+        timerange: f64,
+        limit: f64,
+    },
+
+    // Most likely the CPU went off-line between us listing and reading it
+    #[error("failed to find cpu:{0}:sys")]
+    MissingCpuSys(CpuId),
+
+    #[error("failed to get {field} from {kstat}")]
+    MissingField { kstat: String, field: String },
+
+    #[error("failed to read {kstat}: {source}")]
+    KstatRead {
+        kstat: String,
+        source: kstat_rs::Error,
+    },
+}
+
+impl StatError {
+    // Whether the failure suggests the kstat chain has changed under us, in which case any stats
+    // we're holding on to can't be compared with what comes next.
+    pub fn resets_window(&self) -> bool {
+        matches!(
+            self,
+            StatError::MissingCpuSys(_)
+                | StatError::MissingField { .. }
+                | StatError::KstatRead { .. }
+        )
+    }
+}
+
+// Problems with the command line. Any of these gets the usage message.
+#[derive(Debug, Error)]
+pub enum UsageError {
+    #[error("unknown option '{0}'")]
+    UnknownOption(String),
+
+    #[error("option '{0}' needs a value")]
+    MissingValue(String),
+
+    #[error("option '{0}' doesn't take a value")]
+    UnexpectedValue(String),
+
+    #[error("option '{opt}' needs a whole number of seconds, not '{value}'")]
+    BadValue { opt: String, value: String },
+
+    #[error("option '{0}' is not yet supported")]
+    Unsupported(String),
+}
+
+// Reasons a config file can't be used. At startup these are fatal.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("cannot parse {path}: {source}")]
+    Parse {
+        path: String,
+        source: Box<toml::de::Error>,
+    },
+
+    #[error("invalid {field}: {reason}")]
+    Invalid { field: &'static str, reason: String },
+}
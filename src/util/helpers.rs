@@ -1,61 +1,82 @@
-use crate::util::is_apic;
+use crate::util::actuator::Actuator;
+use crate::util::constants::SYSLOG_PROCESS_NAME;
+use crate::util::shutdown::Shutdown;
+#[cfg(not(target_os = "illumos"))]
+use crate::util::syslog::DatagramTransport;
+#[cfg(target_os = "illumos")]
+use crate::util::syslog::StreamsTransport;
+use crate::util::syslog::SyslogLogger;
 use anyhow::anyhow;
 use kstat_rs::{Ctl, Kstat, NamedData};
 use signal_hook::consts::signal::*;
 use signal_hook::iterator::Signals;
 use simplelog::{ColorChoice, Config, LevelFilter, TermLogger, TerminalMode};
-use std::env;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{self, IsTerminal};
 use std::sync::Arc;
 use std::thread;
 
-pub fn setup_signal_handler() -> Arc<AtomicBool> {
-    let gotsig = Arc::new(AtomicBool::new(false));
+pub fn setup_signal_handler() -> Arc<Shutdown> {
+    let shutdown = Arc::new(Shutdown::default());
     let mut signals = Signals::new([SIGINT, SIGHUP, SIGTERM]).expect("Failed to register signals");
 
-    let gotsig_clone = Arc::clone(&gotsig);
+    let shutdown_clone = Arc::clone(&shutdown);
 
+    // SIGHUP is what svcadm refresh sends. Everything else we catch means it's time to go.
     thread::spawn(move || {
         for sig in signals.forever() {
-            println!("Received signal: {}", sig);
-            gotsig_clone.store(true, Ordering::SeqCst);
+            match sig {
+                SIGHUP => shutdown_clone.request_reload(sig),
+                _ => shutdown_clone.request(sig),
+            }
         }
     });
 
-    gotsig
+    shutdown
 }
 
-pub fn in_debug_mode() -> anyhow::Result<bool> {
-    let args: Vec<String> = env::args().collect();
+pub fn setup_logger(level: LevelFilter) -> std::result::Result<(), log::SetLoggerError> {
+    // intrd logs to syslog as a daemon, tagging each message with its PID. When we've been
+    // started from a terminal, it's more useful to see what's going on right there.
 
-    // Parse arguments. intrd does not accept any public arguments; the two
-    // arguments below are meant for testing purposes. -D generates a significant
-    // amount of syslog output. -S <filename> loads the filename as a perl
-    // script. That file is expected to implement a kstat "simulator" which
-    // can be used to feed information to intrd and verify intrd's responses.
-
-    if args.len() > 1 {
-        return Err(anyhow!("The only valid argument is '-D' or '--debug'."));
+    if !io::stderr().is_terminal() {
+        match syslog_transport() {
+            Ok(transport) => {
+                log::set_boxed_logger(Box::new(SyslogLogger::new(
+                    SYSLOG_PROCESS_NAME,
+                    level,
+                    transport,
+                )))?;
+                log::set_max_level(level);
+                return Ok(());
+            }
+            Err(e) => eprintln!("{}: cannot connect to syslog: {}", SYSLOG_PROCESS_NAME, e),
+        }
     }
 
-    Ok(args.len() == 2 && (args[1] == "-D" || args[1] == "--debug"))
-}
-
-pub fn setup_logger() -> std::result::Result<(), log::SetLoggerError> {
-    // TODO intrd logs to syslog. Logging to syslog from Rust on illumos is a pain, because none
-    // of the syslog crates I can find will write STREAMS. So we'll just use simplelog for now. In
-    // the unlikely event of this thing ever being completed and proven good, I'll write a syslog
-    // interface.
     TermLogger::init(
-        LevelFilter::Debug,
+        level,
         Config::default(),
         TerminalMode::Mixed,
         ColorChoice::Auto,
     )
 }
 
+#[cfg(target_os = "illumos")]
+fn syslog_transport() -> io::Result<StreamsTransport> {
+    StreamsTransport::open()
+}
+
+#[cfg(not(target_os = "illumos"))]
+fn syslog_transport() -> io::Result<DatagramTransport> {
+    DatagramTransport::connect("/dev/log")
+}
+
 // I'm not convinced we need this check. Feels like vestigial SPARC support to me.
-pub fn is_apic_system(ctl: &Ctl, first_stat: &mut Kstat) -> anyhow::Result<bool> {
+pub fn is_apic_system(
+    ctl: &Ctl,
+    first_stat: &mut Kstat,
+    actuator: &dyn Actuator,
+) -> anyhow::Result<bool> {
     let bus_path = match ctl.read(first_stat) {
         Ok(kstat_rs::Data::Named(stat)) => {
             stat.iter()
@@ -73,7 +94,7 @@ pub fn is_apic_system(ctl: &Ctl, first_stat: &mut Kstat) -> anyhow::Result<bool>
     };
 
     match bus_path {
-        Some(path) => is_apic::is_apic(path),
+        Some(path) => actuator.is_apic(path),
         None => Err(anyhow!("Could not find buspath kstat")),
     }
 }
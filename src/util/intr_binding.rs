@@ -1,40 +1,132 @@
-// use nix::libc;
-// use std::fs::OpenOptions;
-// use std::io;
-// use std::os::fd::AsRawFd;
-// use std::os::unix::io::RawFd;
-
-// #[repr(C)]
-// struct PciToolIntrSet {
-//     old_cpu: i32,
-//     ino: i32,
-//     cpu_id: i32,
-//     flags: u32,
-//     user_version: u32,
-// }
-
-// pub fn intrmove(path: &str, oldcpu: i32, ino: i32, cpu: i32, num_ino: i32) -> io::Result<()> {
-//     let fd = open_dev(path)?;
-
-//     let flags = if num_ino > 1 {
-//         PCITOOL_INTR_FLAG_SET_GROUP
-//     } else {
-//         0
-//     };
-
-//     let iset = PciToolIntrSet {
-//         cpu_id: cpu,
-//         flags,
-//         ino,
-//         old_cpu: oldcpu,
-//         user_version: PCITOOL_VERSION,
-//     };
-
-//     let ret = unsafe { libc::ioctl(fd, PCITOOL_DEVICE_SET_INTR, &iset) };
-
-//     if ret == -1 {
-//         Err(io::Error::last_os_error())
-//     } else {
-//         Ok(())
-//     }
-// }
+use crate::util::constants::{
+    PCITOOL_DEVICE_SET_INTR, PCITOOL_INTR_FLAG_SET_GROUP, PCITOOL_VERSION,
+};
+use crate::util::types::CpuId;
+use libc::ioctl;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::fd::AsRawFd;
+use thiserror::Error;
+
+// pcitool_intr_set_t from <sys/pci_tools.h>
+#[repr(C)]
+struct PciToolIntrSet {
+    user_version: u16,
+    drvr_version: u16,
+    ino: u32,
+    msi: u32,
+    cpu_id: u32,
+    old_cpu: u32,
+    flags: u32,
+    status: u32,
+}
+
+#[derive(Debug, Error)]
+pub enum IntrMoveError {
+    #[error("cannot open {path}: {source}")]
+    Open { path: String, source: io::Error },
+
+    // The ino, cpu or flags were not acceptable to the nexus driver. Also what we get if the
+    // interrupt isn't on the CPU we think it's on.
+    #[error("invalid argument moving ino {ino} to cpu {cpu}")]
+    InvalidArgument { ino: u64, cpu: CpuId },
+
+    // The interrupt or target CPU doesn't exist (any more)
+    #[error("no such interrupt ino {ino} or cpu {cpu}")]
+    NoSuchDevice { ino: u64, cpu: CpuId },
+
+    #[error("interrupt ino {ino} is busy")]
+    Busy { ino: u64 },
+
+    #[error("not permitted to move interrupts")]
+    PermissionDenied,
+
+    #[error("failed to move ino {ino} to cpu {cpu}: {source}")]
+    Other {
+        ino: u64,
+        cpu: CpuId,
+        source: io::Error,
+    },
+}
+
+// Only root can open a nexus's intr node, so not being allowed to is the same as not being allowed
+// to move the interrupt.
+fn open_error(path: String, source: io::Error) -> IntrMoveError {
+    match source.raw_os_error() {
+        Some(libc::EPERM | libc::EACCES) => IntrMoveError::PermissionDenied,
+        _ => IntrMoveError::Open { path, source },
+    }
+}
+
+// Retarget interrupt ino, on the bus at buspath, from old_cpu to new_cpu. If num_ino is more than
+// one, this is an MSI group on an APIC system, and all its vectors move together.
+pub fn move_interrupt(
+    buspath: &str,
+    old_cpu: CpuId,
+    ino: u64,
+    new_cpu: CpuId,
+    num_ino: u64,
+) -> Result<(), IntrMoveError> {
+    let path = format!("/devices{}:intr", buspath);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .map_err(|source| open_error(path, source))?;
+
+    let flags = if num_ino > 1 {
+        PCITOOL_INTR_FLAG_SET_GROUP
+    } else {
+        0
+    };
+
+    let mut iset = PciToolIntrSet {
+        user_version: PCITOOL_VERSION,
+        drvr_version: 0,
+        ino: ino as u32,
+        msi: 0,
+        cpu_id: new_cpu as u32,
+        old_cpu: old_cpu as u32,
+        flags,
+        status: 0,
+    };
+
+    let ret = unsafe { ioctl(file.as_raw_fd(), PCITOOL_DEVICE_SET_INTR as _, &mut iset) };
+
+    if ret == -1 {
+        let err = io::Error::last_os_error();
+
+        return Err(match err.raw_os_error() {
+            Some(libc::EINVAL) => IntrMoveError::InvalidArgument { ino, cpu: new_cpu },
+            Some(libc::ENXIO) => IntrMoveError::NoSuchDevice { ino, cpu: new_cpu },
+            Some(libc::EBUSY) => IntrMoveError::Busy { ino },
+            Some(libc::EPERM | libc::EACCES) => IntrMoveError::PermissionDenied,
+            _ => IntrMoveError::Other {
+                ino,
+                cpu: new_cpu,
+                source: err,
+            },
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_error() {
+        for errno in [libc::EPERM, libc::EACCES] {
+            let err = open_error(
+                "/devices/pci@0,0:intr".to_string(),
+                io::Error::from_raw_os_error(errno),
+            );
+            assert!(matches!(err, IntrMoveError::PermissionDenied));
+        }
+
+        let err = move_interrupt("/no/such/bus", 0, 38, 1, 1).unwrap_err();
+        assert!(matches!(err, IntrMoveError::Open { .. }));
+    }
+}
@@ -33,7 +33,7 @@ pub fn is_apic(buspath: &str) -> anyhow::Result<bool> {
         ctlr_type: 0,
     };
 
-    let ret = unsafe { ioctl(fd, PCITOOL_SYSTEM_INTR_INFO.try_into().unwrap(), &mut iinfo) };
+    let ret = unsafe { ioctl(fd, PCITOOL_SYSTEM_INTR_INFO as _, &mut iinfo) };
 
     if ret == -1 {
         return Err(anyhow!(io::Error::last_os_error()));
@@ -41,29 +41,3 @@ pub fn is_apic(buspath: &str) -> anyhow::Result<bool> {
 
     Ok(iinfo.ctlr_type == PCITOOL_CTLR_TYPE_PCPLUSMP || iinfo.ctlr_type == PCITOOL_CTLR_TYPE_APIX)
 }
-
-// pub fn intrmove(path: &str, oldcpu: i32, ino: i32, cpu: i32, num_ino: i32) -> io::Result<()> {
-//     let fd = open_dev(path)?;
-
-//     let flags = if num_ino > 1 {
-//         PCITOOL_INTR_FLAG_SET_GROUP
-//     } else {
-//         0
-//     };
-
-//     let iset = PciToolIntrSet {
-//         cpu_id: cpu,
-//         flags,
-//         ino,
-//         old_cpu: oldcpu,
-//         user_version: PCITOOL_VERSION,
-//     };
-
-//     let ret = unsafe { libc::ioctl(fd, PCITOOL_DEVICE_SET_INTR, &iset) };
-
-//     if ret == -1 {
-//         Err(io::Error::last_os_error())
-//     } else {
-//         Ok(())
-//     }
-// }
@@ -1,5 +1,15 @@
+pub mod actuator;
+pub mod cli;
+pub mod config;
 pub mod constants;
+pub mod errors;
 pub mod helpers;
-// pub mod intr_binding;
+pub mod intr_binding;
 pub mod is_apic;
+pub mod original_bindings;
+pub mod shutdown;
+pub mod sleep_time;
+pub mod stuck;
+pub mod syslog;
+pub mod topology;
 pub mod types;
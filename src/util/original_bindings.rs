@@ -0,0 +1,100 @@
+use crate::util::actuator::Actuator;
+use crate::util::types::{Cookie, CpuId, GotStat, Move};
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone)]
+struct Binding {
+    original: CpuId, // cpu the ivec was on when we first saw it
+    current: CpuId,  // cpu the ivec is on now, as far as we know
+    moved: bool,     // we have moved this ivec at least once
+    name: String,
+    buspath: String,
+    ino: u64,
+    num_ino: u64,
+}
+
+// Remembers where the kernel put each ivec before intrd got its hands on it, so that the
+// bindings can be put back the way they were on the way out.
+#[derive(Debug, Default)]
+pub struct OriginalBindings {
+    bindings: BTreeMap<Cookie, Binding>,
+}
+
+impl OriginalBindings {
+    // Note where every ivec in stat is bound. The first sighting of an ivec is its original
+    // binding; later ones only tell us where it is now.
+    pub fn record(&mut self, stat: &GotStat) {
+        for (cpu_id, cpu) in &stat.cpus {
+            for (inum, ivec) in &cpu.ivecs {
+                self.bindings
+                    .entry(inum.clone())
+                    .and_modify(|binding| binding.current = *cpu_id)
+                    .or_insert_with(|| Binding {
+                        original: *cpu_id,
+                        current: *cpu_id,
+                        moved: false,
+                        name: ivec.name.clone(),
+                        buspath: ivec.buspath.clone(),
+                        ino: ivec.ino,
+                        num_ino: ivec.num_ino,
+                    });
+            }
+        }
+    }
+
+    // Note a move which has been carried out
+    pub fn moved(&mut self, mv: &Move) {
+        if let Some(binding) = self.bindings.get_mut(&mv.inum) {
+            binding.current = mv.to;
+            binding.moved = true;
+        }
+    }
+
+    // The moves which would put back every ivec we have moved away from its original cpu
+    pub fn restore_moves(&self) -> Vec<Move> {
+        self.bindings
+            .iter()
+            .filter(|(_, binding)| binding.moved && binding.current != binding.original)
+            .map(|(inum, binding)| Move {
+                inum: inum.clone(),
+                name: binding.name.clone(),
+                buspath: binding.buspath.clone(),
+                ino: binding.ino,
+                num_ino: binding.num_ino,
+                from: binding.current,
+                to: binding.original,
+            })
+            .collect()
+    }
+
+    // Put back everything we moved. Returns false if any of the moves failed.
+    pub fn restore(&mut self, actuator: &mut dyn Actuator) -> bool {
+        let moves = self.restore_moves();
+        let mut ok = true;
+
+        if moves.is_empty() {
+            debug!("no interrupt bindings to restore");
+            return ok;
+        }
+
+        info!("restoring {} original interrupt binding(s)", moves.len());
+
+        for mv in &moves {
+            debug!("restoring {}", mv);
+
+            match actuator.move_interrupt(mv) {
+                Ok(()) => self.moved(mv),
+                Err(e) => {
+                    warn!(
+                        "Unable to restore buspath {} ino {} to cpu {}: {}",
+                        mv.buspath, mv.ino, mv.to, e
+                    );
+                    ok = false;
+                }
+            }
+        }
+
+        ok
+    }
+}
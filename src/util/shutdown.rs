@@ -0,0 +1,174 @@
+use log::info;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Shared between the signal handling thread and the main loop. A signal only ever asks us to
+// exit, or to reload our configuration; it's up to the main loop to get to a point where that's
+// safe, and never in the middle of retargeting interrupts.
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    state: Mutex<State>,
+    wakeup: Condvar, // notified whenever state changes
+}
+
+#[derive(Debug, Default)]
+struct State {
+    requested: bool,   // a signal has asked us to exit
+    reload: bool,      // a signal has asked us to re-read the config
+    retargeting: bool, // a batch of interrupt moves is under way
+}
+
+impl State {
+    // An exit request only counts once no interrupt moves are under way
+    fn exiting(&self) -> bool {
+        self.requested && !self.retargeting
+    }
+}
+
+impl Shutdown {
+    fn state(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    pub fn request(&self, sig: i32) {
+        let mut state = self.state();
+        state.requested = true;
+
+        if state.retargeting {
+            info!(
+                "received signal {}; exiting once the interrupt moves under way are done",
+                sig
+            );
+        } else {
+            info!("received signal {}; exiting", sig);
+        }
+
+        self.wakeup.notify_all();
+    }
+
+    // Whether it's time to exit. While a batch of interrupt moves is under way, it isn't yet,
+    // whatever signals have arrived.
+    pub fn requested(&self) -> bool {
+        self.state().exiting()
+    }
+
+    pub fn request_reload(&self, sig: i32) {
+        info!("received signal {}; reloading configuration", sig);
+        self.state().reload = true;
+        self.wakeup.notify_all();
+    }
+
+    // Whether a reload has been asked for since we last looked
+    pub fn take_reload(&self) -> bool {
+        std::mem::take(&mut self.state().reload)
+    }
+
+    // Mark the start of a batch of interrupt moves, which lasts until the guard is dropped
+    pub fn retargeting(&self) -> Retargeting<'_> {
+        self.state().retargeting = true;
+        Retargeting { shutdown: self }
+    }
+
+    // Sleep for duration, or until we're asked to exit or reload, whichever comes first
+    pub fn sleep(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        let mut state = self.state();
+
+        while !state.exiting() && !state.reload {
+            let now = Instant::now();
+
+            if now >= deadline {
+                break;
+            }
+
+            state = match self.wakeup.wait_timeout(state, deadline - now) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    // Sleep until we're asked to exit, however long that takes
+    pub fn wait(&self) {
+        let mut state = self.state();
+
+        while !state.exiting() {
+            state = match self.wakeup.wait(state) {
+                Ok(state) => state,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+        }
+    }
+}
+
+pub struct Retargeting<'a> {
+    shutdown: &'a Shutdown,
+}
+
+impl Drop for Retargeting<'_> {
+    fn drop(&mut self) {
+        self.shutdown.state().retargeting = false;
+        self.shutdown.wakeup.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_request() {
+        let shutdown = Shutdown::default();
+
+        shutdown.request_reload(libc::SIGHUP);
+        assert!(!shutdown.requested());
+
+        // A pending reload cuts the sleep short, and is only reported once
+        let start = Instant::now();
+        shutdown.sleep(Duration::from_secs(60));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(shutdown.take_reload());
+        assert!(!shutdown.take_reload());
+    }
+
+    #[test]
+    fn test_sleep_wakes_on_request() {
+        let shutdown = std::sync::Arc::new(Shutdown::default());
+        let signaller = std::sync::Arc::clone(&shutdown);
+
+        // Nothing polls the flags, so the sleep only ends early if the request wakes it
+        let start = Instant::now();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            signaller.request(libc::SIGTERM);
+        });
+        shutdown.sleep(Duration::from_secs(60));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(shutdown.requested());
+
+        shutdown.wait();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_retargeting_defers_shutdown() {
+        let shutdown = std::sync::Arc::new(Shutdown::default());
+        let signaller = std::sync::Arc::clone(&shutdown);
+
+        let retargeting = shutdown.retargeting();
+        std::thread::spawn(move || signaller.request(libc::SIGTERM))
+            .join()
+            .unwrap();
+
+        // The signal is in, but the batch under way has to finish first
+        assert!(!shutdown.requested());
+        shutdown.sleep(Duration::from_millis(10));
+        assert!(!shutdown.requested());
+
+        drop(retargeting);
+        assert!(shutdown.requested());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::util::config::Config;
+use log::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SleepState {
+    Normal,
+    Idle,
+    SingleCpu,
+}
+
+// The original keeps $sleeptime as a global which getstat() and compress_deltas() poke at. This
+// does the same job, but remembers why we're sleeping for as long as we are, and says so when it
+// changes.
+#[derive(Debug)]
+pub struct SleepTime {
+    state: SleepState,
+    normal: u64, // seconds to sleep in each state
+    idle: u64,
+    single_cpu: u64,
+    idle_intr_load: f64, // interrupt load below which we're idle
+}
+
+impl SleepTime {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            state: SleepState::Normal,
+            normal: config.normal_sleep_time,
+            idle: config.idle_sleep_time,
+            single_cpu: config.single_cpu_sleep_time,
+            idle_intr_load: config.idle_intr_load,
+        }
+    }
+
+    // Pick up new sleep times and idle threshold, staying in whatever state we're in
+    pub fn reconfigure(&mut self, config: &Config) {
+        let old = self.secs();
+
+        *self = Self {
+            state: self.state,
+            ..Self::new(config)
+        };
+
+        if self.secs() != old {
+            info!("sleep time {}s -> {}s ({:?})", old, self.secs(), self.state);
+        }
+    }
+
+    pub fn secs(&self) -> u64 {
+        self.secs_in(self.state)
+    }
+
+    fn secs_in(&self, state: SleepState) -> u64 {
+        match state {
+            SleepState::Normal => self.normal,
+            SleepState::Idle => self.idle,
+            SleepState::SingleCpu => self.single_cpu,
+        }
+    }
+
+    pub fn state(&self) -> SleepState {
+        self.state
+    }
+
+    pub fn transition(&mut self, next: SleepState) {
+        if next != self.state {
+            info!(
+                "sleep time {}s -> {}s ({:?} -> {:?})",
+                self.secs(),
+                self.secs_in(next),
+                self.state,
+                next
+            );
+            self.state = next;
+        }
+    }
+
+    // If even the busiest CPU is spending little time on interrupts, there's no hurry to look
+    // again.
+    pub fn after_load(&mut self, high_intrload: f64) {
+        self.transition(if high_intrload < self.idle_intr_load {
+            SleepState::Idle
+        } else {
+            SleepState::Normal
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::constants::IDLE_SLEEP_TIME;
+
+    #[test]
+    fn test_sleep_time_reconfigure() {
+        let mut sleep_time = SleepTime::new(&Config::default());
+        sleep_time.after_load(0.0);
+        assert_eq!(SleepState::Idle, sleep_time.state());
+        assert_eq!(IDLE_SLEEP_TIME, sleep_time.secs());
+
+        let config = Config::parse("idle_sleep_time = 30\nidle_intr_load = 0.2").unwrap();
+        sleep_time.reconfigure(&config);
+        assert_eq!(SleepState::Idle, sleep_time.state());
+        assert_eq!(30, sleep_time.secs());
+
+        // 15% is no longer enough to stop us being idle
+        sleep_time.after_load(0.15);
+        assert_eq!(SleepState::Idle, sleep_time.state());
+    }
+}
@@ -0,0 +1,50 @@
+use crate::util::types::{Cookie, CpuId, CpuState, GotStat, Move};
+use log::{debug, info};
+use std::collections::BTreeMap;
+
+// Ivecs which the kernel refused to move off a cpu that shouldn't have interrupts. Left alone,
+// they'd keep goodness at its worst, and we'd plan the same doomed evacuation every window. So
+// they're treated as pinned instead, for as long as they stay where they are and that cpu's state
+// doesn't change.
+#[derive(Debug, Default)]
+pub struct Stuck {
+    ivecs: BTreeMap<Cookie, (CpuId, CpuState)>,
+}
+
+impl Stuck {
+    // Note that mv, which was to evacuate an ivec from a cpu in state, didn't happen
+    pub fn failed(&mut self, mv: &Move, state: &CpuState) {
+        info!(
+            "leaving {} (\"{}\") on cpu {}, which won't let it go",
+            mv.name, mv.inum, mv.from
+        );
+        self.ivecs.insert(mv.inum.clone(), (mv.from, state.clone()));
+    }
+
+    // Pin whichever stuck ivecs are still stuck in stat, and forget the rest
+    pub fn pin(&mut self, stat: &mut GotStat) {
+        self.ivecs.retain(|inum, (cpu_id, state)| {
+            let ivec = stat
+                .cpus
+                .get_mut(cpu_id)
+                .filter(|cpu| cpu.state == *state)
+                .and_then(|cpu| cpu.ivecs.get_mut(inum));
+
+            match ivec {
+                Some(ivec) => {
+                    ivec.pinned = true;
+                    true
+                }
+                None => {
+                    debug!("\"{}\" is no longer stuck on cpu {}", inum, cpu_id);
+                    false
+                }
+            }
+        });
+    }
+
+    // Start afresh, for instance because which cpus are excluded may have changed
+    pub fn clear(&mut self) {
+        self.ivecs.clear();
+    }
+}
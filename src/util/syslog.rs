@@ -0,0 +1,225 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io;
+#[cfg(any(test, not(target_os = "illumos")))]
+use std::os::unix::net::UnixDatagram;
+#[cfg(any(test, not(target_os = "illumos")))]
+use std::path::Path;
+use std::process;
+use std::sync::Mutex;
+
+// Somewhere to put a formatted syslog message. pri is the facility and severity, combined as
+// syslog(3C) combines them, and line is everything that follows.
+pub trait Transport: Send {
+    fn send(&mut self, pri: i32, line: &str) -> io::Result<()>;
+}
+
+// The socket interface used by syslogd on Linux and the BSDs, which takes "<pri>line"
+#[cfg(any(test, not(target_os = "illumos")))]
+pub struct DatagramTransport {
+    socket: UnixDatagram,
+}
+
+#[cfg(any(test, not(target_os = "illumos")))]
+impl DatagramTransport {
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self { socket })
+    }
+}
+
+#[cfg(any(test, not(target_os = "illumos")))]
+impl Transport for DatagramTransport {
+    fn send(&mut self, pri: i32, line: &str) -> io::Result<()> {
+        self.socket.send(format!("<{}>{}", pri, line).as_bytes())?;
+        Ok(())
+    }
+}
+
+// illumos has no syslog socket. Like syslog(3C), we putmsg(2) to the log driver through
+// /dev/conslog, with the priority in a log_ctl control part and the message as the data.
+#[cfg(target_os = "illumos")]
+pub struct StreamsTransport {
+    file: std::fs::File,
+}
+
+#[cfg(target_os = "illumos")]
+mod streams {
+    use libc::{c_char, c_int, c_short};
+
+    // sys/strlog.h
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct LogCtl {
+        pub mid: c_short,
+        pub sid: c_short,
+        pub level: c_char,
+        pub flags: c_short,
+        pub ltime: i32, // clock32_t
+        pub ttime: i32, // time32_t
+        pub seq_no: c_int,
+        pub pri: c_int,
+    }
+
+    // sys/stropts.h
+    #[repr(C)]
+    pub struct StrBuf {
+        pub maxlen: c_int,
+        pub len: c_int,
+        pub buf: *mut c_char,
+    }
+
+    extern "C" {
+        pub fn putmsg(
+            fildes: c_int,
+            ctlptr: *const StrBuf,
+            dataptr: *const StrBuf,
+            flags: c_int,
+        ) -> c_int;
+    }
+}
+
+#[cfg(target_os = "illumos")]
+impl StreamsTransport {
+    pub const PATH: &'static str = "/dev/conslog";
+
+    pub fn open() -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new().write(true).open(Self::PATH)?;
+        Ok(Self { file })
+    }
+}
+
+#[cfg(target_os = "illumos")]
+impl Transport for StreamsTransport {
+    fn send(&mut self, pri: i32, line: &str) -> io::Result<()> {
+        use std::ffi::CString;
+        use std::os::fd::AsRawFd;
+
+        let mut hdr = streams::LogCtl {
+            pri,
+            ..Default::default()
+        };
+        let ctl = streams::StrBuf {
+            maxlen: 0,
+            len: std::mem::size_of::<streams::LogCtl>() as _,
+            buf: &mut hdr as *mut streams::LogCtl as *mut _,
+        };
+
+        // The log driver wants the terminating NUL
+        let line = CString::new(line.replace('\0', ""))?;
+        let dat = streams::StrBuf {
+            maxlen: 0,
+            len: line.as_bytes_with_nul().len() as _,
+            buf: line.as_ptr() as *mut _,
+        };
+
+        // SAFETY: both strbufs point at buffers which outlive the call, and putmsg only reads
+        // them.
+        if unsafe { streams::putmsg(self.file.as_raw_fd(), &ctl, &dat, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+// A log::Log which behaves like openlog(ident, LOG_PID, LOG_DAEMON) and a LOG_UPTO() mask
+pub struct SyslogLogger<T: Transport> {
+    ident: String,
+    pid: u32,
+    level: LevelFilter,
+    transport: Mutex<T>,
+}
+
+impl<T: Transport> SyslogLogger<T> {
+    pub fn new(ident: &str, level: LevelFilter, transport: T) -> Self {
+        Self {
+            ident: ident.to_string(),
+            pid: process::id(),
+            level,
+            transport: Mutex::new(transport),
+        }
+    }
+
+    // The facility and severity a record goes out with. syslog has nothing finer than debug.
+    pub fn priority(level: Level) -> i32 {
+        let severity = match level {
+            Level::Error => libc::LOG_ERR,
+            Level::Warn => libc::LOG_WARNING,
+            Level::Info => libc::LOG_INFO,
+            Level::Debug | Level::Trace => libc::LOG_DEBUG,
+        };
+
+        libc::LOG_DAEMON | severity
+    }
+}
+
+impl<T: Transport> Log for SyslogLogger<T> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format!("{}[{}]: {}", self.ident, self.pid, record.args());
+        let pri = Self::priority(record.level());
+        let mut transport = match self.transport.lock() {
+            Ok(transport) => transport,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        // As syslog(3C) does, fall back to the console if the logger won't have it
+        if transport.send(pri, &line).is_err() {
+            eprintln!("{}", line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Record;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_syslog_logger() {
+        let path = std::env::temp_dir().join(format!("intrd-rs-syslog-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let syslogd = UnixDatagram::bind(&path).unwrap();
+        let transport = DatagramTransport::connect(&path).unwrap();
+        let logger = SyslogLogger::new("intrd-rs", LevelFilter::Info, transport);
+        let mut buf = [0u8; 256];
+
+        let log = |level, msg| {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{}", msg))
+                    .build(),
+            )
+        };
+
+        // Below the mask, so nothing is sent
+        log(Level::Debug, "chatter");
+        log(Level::Warn, "Unable to move interrupts");
+        let len = syslogd.recv(&mut buf).unwrap();
+        assert_eq!(
+            format!(
+                "<28>intrd-rs[{}]: Unable to move interrupts",
+                std::process::id()
+            ),
+            String::from_utf8_lossy(&buf[..len])
+        );
+
+        log(Level::Info, "Interrupt assignments optimized");
+        let len = syslogd.recv(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf[..len]).starts_with("<30>intrd-rs["));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
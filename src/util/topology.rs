@@ -0,0 +1,101 @@
+use crate::util::types::CpuId;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Where a cpu sits in the hardware, from cpu_info:<cpuid>:cpu_info<cpuid>
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuInfo {
+    pub chip_id: i64,   // the socket
+    pub core_id: i64,   // cpus sharing a core are SMT siblings
+    pub clock_mhz: i64, // clock_MHz
+    pub brand: String,
+}
+
+// Every cpu the system knows about, whatever its state, and how they're laid out into cores and
+// sockets. cpu_info doesn't change unless cpus are hot-plugged, so this is only read at startup
+// and when the set of cpus changes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Topology {
+    cpus: BTreeMap<CpuId, CpuInfo>,
+}
+
+// socket -> core -> cpus
+pub type Sockets = BTreeMap<i64, BTreeMap<i64, Vec<CpuId>>>;
+
+impl Topology {
+    pub fn new(cpus: BTreeMap<CpuId, CpuInfo>) -> Self {
+        Self { cpus }
+    }
+
+    pub fn cpu(&self, cpu_id: CpuId) -> Option<&CpuInfo> {
+        self.cpus.get(&cpu_id)
+    }
+
+    // The same cpus, grouped by socket and then by core
+    pub fn sockets(&self) -> Sockets {
+        let mut sockets = Sockets::new();
+
+        for (cpu_id, info) in &self.cpus {
+            sockets
+                .entry(info.chip_id)
+                .or_default()
+                .entry(info.core_id)
+                .or_default()
+                .push(*cpu_id);
+        }
+
+        sockets
+    }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sockets = self.sockets();
+        let cores: usize = sockets.values().map(|cores| cores.len()).sum();
+
+        write!(
+            f,
+            "{} socket(s), {} core(s), {} cpu(s)",
+            sockets.len(),
+            cores,
+            self.cpus.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topology() {
+        // Two sockets of two cores, each with a pair of SMT siblings numbered as illumos does
+        let topology = Topology::new(
+            (0..8)
+                .map(|cpu_id| {
+                    let info = CpuInfo {
+                        chip_id: (cpu_id % 4 / 2) as i64,
+                        core_id: (cpu_id % 4) as i64,
+                        clock_mhz: 2400,
+                        brand: "Intel(r) Xeon(r) CPU".to_string(),
+                    };
+                    (cpu_id, info)
+                })
+                .collect(),
+        );
+
+        assert_eq!("2 socket(s), 4 core(s), 8 cpu(s)", topology.to_string());
+        assert_eq!(1, topology.cpu(7).unwrap().chip_id);
+        assert!(topology.cpu(8).is_none());
+
+        let sockets = topology.sockets();
+        assert_eq!(vec![0, 1], sockets.keys().copied().collect::<Vec<_>>());
+        assert_eq!(vec![2, 6], sockets[&1][&2]);
+        assert_eq!(vec![3, 7], sockets[&1][&3]);
+
+        assert_eq!(
+            "0 socket(s), 0 core(s), 0 cpu(s)",
+            Topology::default().to_string()
+        );
+    }
+}
@@ -1 +1,245 @@
-// types
+use crate::util::constants::NANOSEC;
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub type CpuId = i32;
+
+// An ivec is identified by the "buspath ino" pair of the interrupt it describes
+pub type Cookie = String;
+
+#[derive(Debug, Clone)]
+pub struct Ivec {
+    pub time: u64,       // pci_intrs:<ivec#>:<nexus>:time (in nsec)
+    pub pil: u64,        // pci_intrs:<ivec#>:<nexus>:pil
+    pub crtime: u64,     // pci_intrs:<ivec#>:<nexus>:crtime
+    pub ino: u64,        // pci_intrs:<ivec#>:<nexus>:ino
+    pub num_ino: u64, // num inos of single device instance sharing this entry. Will be > 1 on pcplusmp X86 systems for devices with multiple MSI interrupts.
+    pub buspath: String, // pci_intrs:<ivec#>:<nexus>:buspath
+    pub name: String, // pci_intrs:<ivec#>:<nexus>:name
+    pub ihs: u64,     // pci_intrs:<ivec#>:<nexus>:ihs
+    pub pinned: bool, // policy says this ivec must stay where it is
+}
+
+impl Ivec {
+    // Fold in another handler sharing this cookie. If this new interrupt represents a change from
+    // an earlier getstat, make sure that generate_delta will see the change by setting crtime to
+    // the most recent crtime of its components.
+    pub fn add_shared_handler(&mut self, time: u64, crtime: u64, name: &str) {
+        self.time += time;
+        self.name = format!("{}/{}", self.name, name);
+        self.crtime = self.crtime.max(crtime);
+        self.ihs += 1;
+    }
+
+    // The drivers sharing this interrupt, in the order their kstats were read
+    pub fn handlers(&self) -> Vec<&str> {
+        self.name.split('/').collect()
+    }
+}
+
+// cpu_info:<cpuid>:cpu_info<cpuid>:state, as set by p_online(2) and psradm(8). Only an on-line
+// cpu should have interrupts, but the kernel can't always move them away from one which has been
+// taken out of service, so we keep an eye on the rest too.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum CpuState {
+    #[default]
+    OnLine, // runs threads and takes interrupts
+    NoIntr,        // runs threads, but wants no interrupts (psradm -i)
+    OffLine,       // runs nothing (psradm -f)
+    Spare,         // held in reserve, and runs nothing (psradm -s)
+    Faulted,       // taken out of service by fault management
+    PoweredOff,    // psradm -F
+    Other(String), // a state we don't know about
+}
+
+impl CpuState {
+    pub fn parse(state: &str) -> Self {
+        match state {
+            "on-line" => Self::OnLine,
+            "no-intr" => Self::NoIntr,
+            "off-line" => Self::OffLine,
+            "spare" => Self::Spare,
+            "faulted" => Self::Faulted,
+            "powered-off" => Self::PoweredOff,
+            other => Self::Other(other.to_string()),
+        }
+    }
+
+    // Whether do_reconfig() may leave interrupts here, or give it more
+    pub fn takes_interrupts(&self) -> bool {
+        *self == Self::OnLine
+    }
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            Self::OnLine => "on-line",
+            Self::NoIntr => "no-intr",
+            Self::OffLine => "off-line",
+            Self::Spare => "spare",
+            Self::Faulted => "faulted",
+            Self::PoweredOff => "powered-off",
+            Self::Other(state) => state,
+        };
+
+        write!(f, "{}", state)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CpuStat {
+    pub state: CpuState,               // cpu_info:<cpuid>:cpu_info<cpuid>:state
+    pub tot: u64,                      // cpu:<cpuid>:sys:cpu_nsec_{user + kernel + idle}
+    pub crtime: u64,                   // cpu:<cpuid>:sys:crtime
+    pub ivecs: BTreeMap<Cookie, Ivec>, // iterates over pci_intrs::<nexus>:cookie
+}
+
+#[derive(Debug, Clone)]
+pub struct GotStat {
+    pub snaptime: u64,                  // kstat's snaptime
+    pub cpus: BTreeMap<CpuId, CpuStat>, // one entry per cpu with stats, whatever its state
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeltaCpu {
+    pub state: CpuState,               // as of the later of the two stats
+    pub intrs: u64,                    // cpu's movable intr time (sum of "time" for each ivec)
+    pub tot: u64,                      // CPU load from all sources in nsec
+    pub bigintr: u64,                  // largest value of ivecs[].time
+    pub intrload: f64,                 // intrs / tot
+    pub ivecs: BTreeMap<Cookie, Ivec>, // ivecs on this cpu, with time as a delta
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Delta {
+    pub missing: bool,         // the delta's component stats had inconsistencies
+    pub minsnap: u64,          // time of the first kstat snaptime used in this delta
+    pub maxsnap: u64,          // time of the last kstat snaptime used in this delta
+    pub goodness: Option<f64>, // cost function applied to this delta
+    pub avgintrload: f64,      // avg of interrupt load across cpus
+    pub avgintrnsec: f64,      // avg number of nsec spent in interrupts, per cpu
+    pub cpus: BTreeMap<CpuId, DeltaCpu>, // one entry per cpu in the stats
+}
+
+impl DeltaCpu {
+    // Interrupt time which has to stay on this cpu whatever we do
+    pub fn pinned(&self) -> u64 {
+        self.ivecs
+            .values()
+            .filter(|ivec| ivec.pinned)
+            .map(|ivec| ivec.time)
+            .sum()
+    }
+}
+
+impl Delta {
+    // A delta which can't be used, because the stats it came from don't match up
+    pub fn missing(minsnap: u64, maxsnap: u64) -> Self {
+        Self {
+            missing: true,
+            minsnap,
+            maxsnap,
+            ..Default::default()
+        }
+    }
+
+    // The span of time, in seconds, which this delta covers
+    pub fn span(&self) -> f64 {
+        self.maxsnap.saturating_sub(self.minsnap) as f64 / NANOSEC
+    }
+
+    // The interrupt load of the busiest CPU
+    pub fn high_intrload(&self) -> f64 {
+        self.cpus.values().map(|c| c.intrload).fold(0.0, f64::max)
+    }
+}
+
+// A single interrupt reassignment decided on by do_reconfig()
+#[derive(Debug, Clone, PartialEq)]
+pub struct Move {
+    pub inum: Cookie,    // the ivec being moved
+    pub name: String,    // device name(s) of the ivec
+    pub buspath: String, // filename of the directory of the device's bus
+    pub ino: u64,        // interrupt number (or base vector if MSI group)
+    pub num_ino: u64,    // number of interrupt vectors in MSI group
+    pub from: CpuId,
+    pub to: CpuId,
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (\"{}\") cpu {} -> cpu {}",
+            self.name, self.inum, self.from, self.to
+        )?;
+
+        if self.num_ino > 1 {
+            write!(f, " as a group of {}", self.num_ino)?;
+        }
+
+        Ok(())
+    }
+}
+
+// An ivec which do_reconfig() left alone because policy pins it to its cpu
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pinned {
+    pub inum: Cookie,
+    pub name: String,
+    pub cpu: CpuId,
+}
+
+impl fmt::Display for Pinned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (\"{}\") pinned to cpu {}",
+            self.name, self.inum, self.cpu
+        )
+    }
+}
+
+// The outcome of do_reconfig(): what it would move, and how much good it expects that to do
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub goodness: f64,    // goodness of the delta the plan was made from
+    pub newgoodness: f64, // predicted goodness once the moves are applied
+    pub moves: Vec<Move>,
+    pub pinned: Vec<Pinned>, // ivecs which stayed put, whatever the moves would have liked
+    pub nodes: usize,        // find_goal() nodes searched, across the whole plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_shared_handler() {
+        let mut ivec = Ivec {
+            time: 100,
+            pil: 5,
+            crtime: 7,
+            ino: 38,
+            num_ino: 1,
+            buspath: "/pci@0,0".to_string(),
+            name: "e1000g#0".to_string(),
+            ihs: 1,
+            pinned: false,
+        };
+
+        // A second handler on the same cookie, created later
+        ivec.add_shared_handler(50, 9, "igb#1");
+        assert_eq!(150, ivec.time);
+        assert_eq!(9, ivec.crtime);
+        assert_eq!(2, ivec.ihs);
+        assert_eq!("e1000g#0/igb#1", ivec.name);
+        assert_eq!(vec!["e1000g#0", "igb#1"], ivec.handlers());
+
+        // An older one doesn't take crtime backwards
+        ivec.add_shared_handler(0, 3, "ehci#0");
+        assert_eq!(9, ivec.crtime);
+        assert_eq!(3, ivec.ihs);
+    }
+}